@@ -0,0 +1,342 @@
+use crate::{manifest, server};
+use ntex::web::{self, HttpResponse};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Precompressed sidecars, in order of preference: content coding and file suffix.
+const SIDECARS: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+/// Returns the MIME type for a file based on its extension.
+pub fn mime_for(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "application/javascript",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        // Browsers only stream-compile modules served with exactly this type
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Returns true for text-based MIME types, which get a `charset` parameter;
+/// binary types like images and fonts must not.
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/javascript" | "application/json" | "application/xml" | "image/svg+xml"
+        )
+}
+
+/// The `Content-Type` value for a body of type `mime` encoded as UTF-8: with
+/// `; charset=utf-8` for text types, as-is otherwise.
+pub fn utf8_content_type(mime: &str) -> String {
+    if is_text(mime) {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
+
+/// Decodes `%XX` escapes in a URL path. Returns `None` for malformed escapes or
+/// a result that is not UTF-8.
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Maps a request path onto a file below the canonical `root`; a directory maps
+/// to its `index.html`. With `ignore_case`, a path with no exact match falls back
+/// to one matching it case-insensitively. Returns the path as requested, for its
+/// name, and the canonical file to open. Anything that could escape the root
+/// (`..`, absolute paths, symlinks pointing elsewhere), hidden entries (`.env`,
+/// `.git`, the `.breach-cache`), .breach sources and the manifest are refused,
+/// whatever the case of their names.
+fn resolve(root: &Path, request_path: &str, ignore_case: bool) -> Option<(PathBuf, PathBuf)> {
+    let decoded = percent_decode(request_path.trim_start_matches('/'))?;
+    let relative = Path::new(&decoded);
+    if relative.components().any(|c| !matches!(c, Component::Normal(_)) || is_hidden(c)) {
+        return None;
+    }
+    let mut path = root.join(relative);
+    if ignore_case && !path.exists() {
+        path = find_ignoring_case(root, relative)?;
+    }
+    if path.is_dir() {
+        path.push("index.html");
+    }
+    if is_source(&path) {
+        return None;
+    }
+    let file = servable(root, &path)?;
+    Some((path, file))
+}
+
+/// Returns the canonical path of `path` if it is a file that may be served: below
+/// the canonical `root` and no hidden directory of it, and neither a .breach
+/// source nor the manifest. Symlinks are followed, so this holds for the file
+/// actually opened.
+fn servable(root: &Path, path: &Path) -> Option<PathBuf> {
+    let canonical = path.canonicalize().ok()?;
+    let relative = canonical.strip_prefix(root).ok()?;
+    if relative.components().any(is_hidden) || is_source(&canonical) || !canonical.is_file() {
+        return None;
+    }
+    Some(canonical)
+}
+
+fn is_hidden(component: Component) -> bool {
+    component.as_os_str().to_string_lossy().starts_with('.')
+}
+
+// Case-insensitive filesystems open `APP.BREACH` for `app.breach`
+fn is_source(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_ascii_lowercase();
+    name.ends_with(".breach") || manifest::is_manifest(Path::new(&name))
+}
+
+/// Finds the path below `root` whose components match those of `relative`
+/// ignoring case, for `--ignore-case`. Returns `None` when a component matches
+/// nothing, or several entries (`a.css` and `A.css` on a case-sensitive disk).
+fn find_ignoring_case(root: &Path, relative: &Path) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in relative.components() {
+        let exact = path.join(component);
+        if exact.exists() {
+            path = exact;
+            continue;
+        }
+        let wanted = component.as_os_str().to_string_lossy().to_lowercase();
+        let mut matches = fs::read_dir(&path)
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name())
+            .filter(|name| name.to_string_lossy().to_lowercase() == wanted);
+        let name = matches.next()?;
+        if matches.next().is_some() {
+            tracing::debug!("{:?} matches several entries in {:?} ignoring case, serving none", wanted, path);
+            return None;
+        }
+        path.push(name);
+    }
+    Some(path)
+}
+
+/// Returns true if the request's `Accept-Encoding` allows `coding` (or `*`) with
+/// a non-zero quality.
+fn accepts_encoding(req: &web::HttpRequest, coding: &str) -> bool {
+    let Some(header) = req
+        .headers()
+        .get("Accept-Encoding")
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    header.split(',').any(|item| {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        if !name.eq_ignore_ascii_case(coding) && name != "*" {
+            return false;
+        }
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        quality > 0.0
+    })
+}
+
+/// Serves a file from `root` matching the request path, or returns `None` when
+/// there is no such file. A directory serves its `index.html`, after a redirect
+/// adding the trailing slash if the request lacks it. A `.br` or `.gz` sidecar next to the file is sent
+/// instead, with its `Content-Encoding`, when the client accepts that coding.
+/// With `ignore_case`, the path may differ from the file name in case. Only files
+/// [`resolve`] accepts are opened, sidecars included.
+/// Fails if the file exists but cannot be read.
+pub fn serve(req: &web::HttpRequest, root: &Path, ignore_case: bool) -> io::Result<Option<HttpResponse>> {
+    let Ok(root) = root.canonicalize() else {
+        return Ok(None);
+    };
+    let Some((path, file)) = resolve(&root, req.path(), ignore_case) else {
+        return Ok(None);
+    };
+    // A directory is addressed with a trailing slash so relative links in its
+    // index resolve inside it
+    let request_path = req.path();
+    if path.ends_with("index.html") && !request_path.ends_with('/') && !request_path.ends_with("index.html") {
+        return Ok(Some(server::redirect(req, &format!("{}/", request_path))));
+    }
+
+    let mut encoding = None;
+    let mut body = None;
+    for (coding, suffix) in SIDECARS {
+        if !accepts_encoding(req, coding) {
+            continue;
+        }
+        let mut sidecar = path.clone().into_os_string();
+        sidecar.push(".");
+        sidecar.push(suffix);
+        // A sidecar is checked like the file itself before it is opened
+        let Some(sidecar) = servable(&root, Path::new(&sidecar)) else {
+            continue;
+        };
+        if let Ok(bytes) = fs::read(&sidecar) {
+            tracing::debug!("Serving {:?} precompressed with {}", path, coding);
+            encoding = Some(*coding);
+            body = Some(bytes);
+            break;
+        }
+    }
+    let body = match body {
+        Some(body) => body,
+        None => fs::read(&file)?,
+    };
+
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type(mime_for(&path))
+        .header("Cache-Control", "no-cache")
+        .header("Vary", "Accept-Encoding");
+    if let Some(coding) = encoding {
+        response.header("Content-Encoding", coding);
+    }
+    Ok(Some(response.body(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory tree for one test, removed again when dropped
+    struct Site(PathBuf);
+
+    impl Site {
+        fn new(name: &str, files: &[&str]) -> Self {
+            let root = std::env::temp_dir().join(format!("breach-assets-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            for file in files {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, file).unwrap();
+            }
+            Site(root.canonicalize().unwrap())
+        }
+
+        fn resolve(&self, request_path: &str) -> Option<PathBuf> {
+            resolve(&self.0, request_path, false).map(|(_, file)| file)
+        }
+    }
+
+    impl Drop for Site {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn files_and_directory_indexes_resolve() {
+        let site = Site::new("resolve", &["app.css", "docs/index.html", "a b.txt"]);
+        assert_eq!(site.resolve("/app.css"), Some(site.0.join("app.css")));
+        assert_eq!(site.resolve("/docs/"), Some(site.0.join("docs/index.html")));
+        assert_eq!(site.resolve("/a%20b.txt"), Some(site.0.join("a b.txt")));
+        assert_eq!(site.resolve("/missing.css"), None);
+    }
+
+    #[test]
+    fn paths_leaving_the_root_are_refused() {
+        let site = Site::new("escape", &["inner/app.css"]);
+        assert_eq!(site.resolve("/inner/../inner/app.css"), None);
+        assert_eq!(site.resolve("/inner/%2e%2e/inner/app.css"), None);
+        assert_eq!(site.resolve("/inner/%zz"), None);
+        let outside = Site::new("escape-outside", &["secret.txt"]);
+        assert_eq!(resolve(&site.0.join("inner"), "/../../escape-outside/secret.txt", false), None);
+        drop(outside);
+    }
+
+    #[test]
+    fn hidden_entries_and_sources_are_refused() {
+        let site = Site::new("hidden", &[".env", ".git/config", "a/.breach-cache/x", "app.breach", "breach.toml"]);
+        for request_path in ["/.env", "/.git/config", "/a/.breach-cache/x", "/%2egit/config", "/APP.BREACH", "/breach.toml"] {
+            assert_eq!(site.resolve(request_path), None, "{}", request_path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_checked_where_they_point() {
+        let site = Site::new("symlink", &["app.css", ".env", "app.breach"]);
+        let outside = Site::new("symlink-outside", &["secret.txt"]);
+        std::os::unix::fs::symlink(outside.0.join("secret.txt"), site.0.join("secret.txt")).unwrap();
+        std::os::unix::fs::symlink(site.0.join(".env"), site.0.join("env.txt")).unwrap();
+        std::os::unix::fs::symlink(site.0.join("app.breach"), site.0.join("app.txt")).unwrap();
+        std::os::unix::fs::symlink(outside.0.join("secret.txt"), site.0.join("app.css.gz")).unwrap();
+        assert_eq!(site.resolve("/secret.txt"), None);
+        assert_eq!(site.resolve("/env.txt"), None);
+        assert_eq!(site.resolve("/app.txt"), None);
+        assert_eq!(servable(&site.0, &site.0.join("app.css.gz")), None);
+        assert_eq!(servable(&site.0, &site.0.join("app.css")), Some(site.0.join("app.css")));
+    }
+
+    #[test]
+    fn ignoring_case_finds_a_single_match() {
+        let site = Site::new("case", &["Img/Logo.PNG"]);
+        let (_, file) = resolve(&site.0, "/img/logo.png", true).unwrap();
+        assert_eq!(file, site.0.join("Img/Logo.PNG"));
+    }
+
+    #[test]
+    fn wasm_is_served_as_application_wasm() {
+        assert_eq!(mime_for(Path::new("pkg/app_bg.wasm")), "application/wasm");
+        assert_eq!(mime_for(Path::new("APP.WASM")), "application/wasm");
+        assert_eq!(utf8_content_type(mime_for(Path::new("app.wasm"))), "application/wasm");
+    }
+
+    #[test]
+    fn only_text_types_get_a_charset() {
+        for image in ["a.png", "a.jpg", "a.gif", "a.webp", "a.avif", "favicon.ico"] {
+            let mime = mime_for(Path::new(image));
+            assert!(!is_text(mime), "{}", image);
+            assert_eq!(utf8_content_type(mime), mime);
+        }
+        assert_eq!(utf8_content_type("text/html"), "text/html; charset=utf-8");
+        assert_eq!(utf8_content_type("text/css"), "text/css; charset=utf-8");
+        assert_eq!(utf8_content_type("application/javascript"), "application/javascript; charset=utf-8");
+        assert_eq!(utf8_content_type("image/svg+xml"), "image/svg+xml; charset=utf-8");
+        assert_eq!(utf8_content_type("font/woff2"), "font/woff2");
+    }
+}
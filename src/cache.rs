@@ -0,0 +1,269 @@
+use crate::parser::{PreparedContent, SectionFingerprints, SectionSizes, ServedBodies, ServedMedia, ServedVariant};
+use ntex::util::Bytes;
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// File name of the compile cache written next to the source with `--cache`.
+pub const CACHE_NAME: &str = ".breach-cache";
+
+/// Start of every cache file. The version is bumped whenever the layout changes,
+/// so caches written by other versions are ignored instead of misread.
+const MAGIC: &[u8] = b"breach-cache 5\n";
+
+/// Path of the cache for the .breach file at `source`.
+pub fn path_for(source: &Path) -> PathBuf {
+    source.parent().unwrap_or(Path::new(".")).join(CACHE_NAME)
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u64(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn put_optional(out: &mut Vec<u8>, bytes: Option<&[u8]>) {
+    out.push(bytes.is_some() as u8);
+    put(out, bytes.unwrap_or_default());
+}
+
+fn put_strings(out: &mut Vec<u8>, strings: &[String]) {
+    put_u64(out, strings.len() as u64);
+    for s in strings {
+        put(out, s.as_bytes());
+    }
+}
+
+/// Reads the fields written by the `put` functions.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        usize::try_from(self.u64()?).ok()
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.usize()?;
+        self.take(len)
+    }
+
+    fn body(&mut self) -> Option<Bytes> {
+        self.bytes().map(Bytes::copy_from_slice)
+    }
+
+    fn optional(&mut self) -> Option<Option<Bytes>> {
+        let present = self.take(1)?[0] != 0;
+        let body = self.body()?;
+        Some(present.then_some(body))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?.to_vec()).ok()
+    }
+
+    fn strings(&mut self) -> Option<Vec<String>> {
+        (0..self.u64()?).map(|_| self.string()).collect()
+    }
+}
+
+/// Serializes what [`load`] restores: everything `prepare()` produced but the
+/// parsed sections, which are read from the source again, and the timings.
+fn encode(prepared: &PreparedContent, content_key: u64, styling_source_hash: u64) -> Vec<u8> {
+    let bodies = &prepared.bodies;
+    let mut out = MAGIC.to_vec();
+    put_u64(&mut out, content_key);
+    put_u64(&mut out, styling_source_hash);
+    put_u64(&mut out, prepared.fingerprint);
+    let sections = &prepared.section_fingerprints;
+    for fingerprint in [sections.markup, sections.styling, sections.script] {
+        put_u64(&mut out, fingerprint);
+    }
+    let sizes = &prepared.source_sizes;
+    for size in [sizes.markup, sizes.styling, sizes.script] {
+        put_u64(&mut out, size as u64);
+    }
+    put_strings(&mut out, &prepared.errors);
+    put_strings(&mut out, &prepared.styling_errors);
+    out.push(bodies.hashed_names as u8);
+    for body in [&bodies.html, &bodies.styling, &bodies.script, &bodies.favicon] {
+        put_optional(&mut out, body.as_deref());
+    }
+    put_u64(&mut out, bodies.media.len() as u64);
+    for media in &bodies.media {
+        put(&mut out, media.media.as_bytes());
+        put(&mut out, media.slug.as_bytes());
+        put(&mut out, &media.body);
+    }
+    put_u64(&mut out, bodies.variants.len() as u64);
+    for variant in &bodies.variants {
+        put(&mut out, variant.name.as_bytes());
+        put(&mut out, variant.route.as_bytes());
+        put_optional(&mut out, variant.html.as_deref());
+        put_optional(&mut out, variant.styling.as_deref());
+    }
+    out
+}
+
+fn decode(data: &[u8]) -> Option<PreparedContent> {
+    let mut reader = Reader(data.strip_prefix(MAGIC)?);
+    let content_key = reader.u64()?;
+    let styling_source_hash = reader.u64()?;
+    let fingerprint = reader.u64()?;
+    let section_fingerprints = SectionFingerprints {
+        markup: reader.u64()?,
+        styling: reader.u64()?,
+        script: reader.u64()?,
+    };
+    let source_sizes = SectionSizes {
+        markup: reader.usize()?,
+        styling: reader.usize()?,
+        script: reader.usize()?,
+    };
+    let errors = reader.strings()?;
+    let styling_errors = reader.strings()?;
+    let hashed_names = reader.take(1)?[0] != 0;
+    let html = reader.optional()?;
+    let styling = reader.optional()?;
+    let script = reader.optional()?;
+    let favicon = reader.optional()?;
+    let media = (0..reader.u64()?)
+        .map(|_| {
+            Some(ServedMedia {
+                media: reader.string()?,
+                slug: reader.string()?,
+                body: reader.body()?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let variants = (0..reader.u64()?)
+        .map(|_| {
+            Some(ServedVariant {
+                name: reader.string()?,
+                route: reader.string()?,
+                html: reader.optional()?,
+                styling: reader.optional()?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    // Anything left over means the layout isn't the one read here
+    if !reader.0.is_empty() {
+        return None;
+    }
+
+    Some(PreparedContent {
+        fingerprint,
+        errors,
+        styling_errors,
+        source_sizes,
+        section_fingerprints,
+        bodies: ServedBodies { html, styling, script, media, variants, favicon, hashed_names },
+        styling_source_hash: Some(styling_source_hash),
+        content_key: Some(content_key),
+        ..PreparedContent::default()
+    })
+}
+
+/// Loads the cache at `path` as content to pass to `prepare()` as `previous`:
+/// when the source and options are unchanged, the cached outputs are served
+/// without compiling anything; when only the styling sources still hash the
+/// same, their compiled CSS is reused. A missing, unreadable or outdated cache
+/// gives `None`.
+pub fn load(path: &Path) -> Option<PreparedContent> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            tracing::warn!("Failed to read cache {:?}: {}", path, e);
+            return None;
+        }
+    };
+    let cached = decode(&data);
+    if cached.is_none() {
+        tracing::debug!("Ignoring cache {:?} in an unknown format", path);
+    }
+    cached
+}
+
+/// Writes the prepared outputs of `prepared` to the cache at `path`. Does
+/// nothing for content that hasn't been prepared.
+pub fn store(path: &Path, prepared: &PreparedContent) -> io::Result<()> {
+    match prepared.content_key.zip(prepared.styling_source_hash) {
+        Some((content_key, styling_source_hash)) => fs::write(path, encode(prepared, content_key, styling_source_hash)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_breach_content, prepare, PrepareOptions};
+
+    const SOURCE: &str = "¦html\n<p>a</p>\n¦scss\np { b { top: 0 } }\n¦css media=print\np { top: 1 }\n¦css@dark\np { top: 2 }\n¦scss\nq { top: $missing }\n";
+
+    fn prepared(source: &str, previous: Option<&PreparedContent>) -> PreparedContent {
+        prepare(parse_breach_content(source), &PrepareOptions::default(), previous)
+    }
+
+    #[test]
+    fn everything_prepared_survives_a_round_trip() {
+        let original = prepared(SOURCE, None);
+        assert_eq!(original.bodies.media.len(), 1);
+        assert_eq!(original.bodies.variants.len(), 1);
+        assert_eq!(original.styling_errors.len(), 1);
+        let data = encode(&original, original.content_key.unwrap(), original.styling_source_hash.unwrap());
+        let restored = decode(&data).unwrap();
+        assert_eq!(format!("{:?}", restored.bodies), format!("{:?}", original.bodies));
+        assert_eq!(restored.errors, original.errors);
+        assert_eq!(restored.styling_errors, original.styling_errors);
+        assert_eq!(restored.fingerprint, original.fingerprint);
+        assert_eq!(restored.section_fingerprints, original.section_fingerprints);
+        assert_eq!(restored.source_sizes, original.source_sizes);
+        assert_eq!(restored.content_key, original.content_key);
+        assert_eq!(restored.styling_source_hash, original.styling_source_hash);
+    }
+
+    #[test]
+    fn truncated_or_foreign_files_are_rejected() {
+        let original = prepared(SOURCE, None);
+        let data = encode(&original, 1, 2);
+        for len in 0..data.len() {
+            assert!(decode(&data[..len]).is_none(), "{} of {} bytes", len, data.len());
+        }
+        let mut longer = data.clone();
+        longer.push(0);
+        assert!(decode(&longer).is_none());
+        let old = [b"breach-cache 4\n".as_slice(), &data[MAGIC.len()..]].concat();
+        assert!(decode(&old).is_none());
+    }
+
+    #[test]
+    fn an_unchanged_source_is_served_from_the_cache() {
+        let original = prepared(SOURCE, None);
+        let mut cached = decode(&encode(&original, original.content_key.unwrap(), 0)).unwrap();
+        cached.bodies.script = Some(Bytes::from_static(b"cached"));
+        let restarted = prepared(SOURCE, Some(&cached));
+        assert_eq!(restarted.bodies.script.as_deref(), Some(b"cached".as_slice()));
+        assert_eq!(restarted.parsed.markup.as_deref(), Some("<p>a</p>"));
+        let edited = prepared(&SOURCE.replace("<p>a</p>", "<p>b</p>"), Some(&cached));
+        assert_eq!(edited.bodies.script, None);
+        assert_ne!(edited.fingerprint, original.fingerprint);
+    }
+}
@@ -0,0 +1,517 @@
+use crate::config::{BreachConfig, ScriptType};
+use crate::fetch;
+use crate::parser::{
+    PrepareOptions, ReloadMode, ReloadTarget, DEFAULT_MAX_FILE_SIZE, DEFAULT_RECONNECT_ATTEMPTS, DEFAULT_RELOAD_MESSAGE,
+};
+use crate::output::{OnceOutput, WriteFormat};
+use crate::server::{IndexRedirect, SECURITY_HEADERS};
+use clap::{Parser, Subcommand};
+use ntex::http::header::{HeaderName, HeaderValue};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+
+/// Port the server listens on unless `--port` is given.
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Command line options for the B⧸REACH server.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "b-reach", version, about = "The single-file web development ecosystem.")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The .breach file (or `breach.toml` manifest) to serve, or a directory to
+    /// look for one in. Defaults to `index.breach`, `main.breach`, the first
+    /// `.breach` file in the working directory or a `breach.toml` there. An
+    /// `http://` or `https://` URL is fetched and served unwatched; fetching runs
+    /// `curl`, which must be installed.
+    #[arg(value_name = "FILE|DIR|URL", conflicts_with = "stdin")]
+    pub file: Option<PathBuf>,
+
+    /// File name to look for first when no FILE (or a DIR) is given, before
+    /// `index.breach`, `main.breach` and finally the first `.breach` file by name.
+    #[arg(long, value_name = "NAME")]
+    pub entry: Option<String>,
+
+    /// Run in this directory, as if started there: discovery, FILE|DIR and other
+    /// relative paths (`--out-dir`, `--watch-extra`, ...) resolve against it.
+    #[arg(long, value_name = "DIR")]
+    pub cwd: Option<PathBuf>,
+
+    /// Prepare the content once, print it to stdout and exit, without watching
+    /// or serving. Prints the HTML unless `--once=css`, `--once=js` or
+    /// `--once=all` is given.
+    #[arg(
+        long,
+        value_name = "OUTPUT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "html"
+    )]
+    pub once: Option<OnceOutput>,
+
+    /// Print the settings in effect after merging the command line, front-matter
+    /// and `¦config`, each with where it came from, and exit.
+    #[arg(long, conflicts_with_all = ["once", "no_serve"])]
+    pub show_config: bool,
+
+    /// Address to listen on. `localhost` binds both 127.0.0.1 and [::1];
+    /// `0.0.0.0` or `::` binds every interface on both stacks.
+    #[arg(long, default_value = "localhost")]
+    pub host: String,
+
+    /// Port to listen on.
+    #[arg(long, default_value_t = DEFAULT_PORT)]
+    pub port: u16,
+
+    /// When the port is taken, try up to N following ports (default 10) and use
+    /// the first free one, e.g. to run several servers side by side.
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "10")]
+    pub port_auto: Option<u16>,
+
+    /// Listen on every interface (same as `--host 0.0.0.0`), e.g. to preview on
+    /// a phone on the same network. Anyone on the network can then reach the server.
+    #[arg(long, conflicts_with = "host")]
+    pub public: bool,
+
+    /// How open pages react to changes: `auto` reloads immediately, `manual`
+    /// shows a "content changed" banner to click instead.
+    #[arg(long, default_value = "auto")]
+    pub reload_mode: ReloadMode,
+
+    /// What pages shown in an iframe reload: `self` reloads just the frame,
+    /// `parent` posts a `{type: 'breach:reload', url}` message to the embedding
+    /// page instead, so a tool embedding the preview decides what to reload.
+    #[arg(long, value_name = "TARGET", default_value = "self")]
+    pub reload_target: ReloadTarget,
+
+    /// How many times in a row open pages try to reconnect to a server that went
+    /// away before giving up and showing a "disconnected" badge. 0 gives up on
+    /// the first drop.
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_RECONNECT_ATTEMPTS)]
+    pub reconnect_attempts: u32,
+
+    /// Text sent over `/ws` when the content changed, for custom clients
+    /// listening for a specific message.
+    #[arg(long, value_name = "TEXT", default_value = DEFAULT_RELOAD_MESSAGE, value_parser = parse_reload_message, conflicts_with = "reload_json")]
+    pub reload_message: String,
+
+    /// Send reload messages as JSON, `{"type":"reload","fingerprint":"..."}`,
+    /// instead of the `--reload-message` text.
+    #[arg(long)]
+    pub reload_json: bool,
+
+    /// Serve the raw SCSS/Stylus source when it fails to compile instead of
+    /// dropping the styles and showing the error overlay.
+    #[arg(long)]
+    pub style_fallback: bool,
+
+    /// Treat any compile error as a failure instead of degrading: no raw-source
+    /// fallback, no reuse of the last good CSS, and a non-zero exit in
+    /// `--no-serve` mode. Served pages still show the error overlay.
+    #[arg(long, conflicts_with = "style_fallback")]
+    pub strict: bool,
+
+    /// Remove regular `/* ... */` comments from compiled SCSS. `/*! ... */`
+    /// comments (e.g. license headers) are kept either way.
+    #[arg(long)]
+    pub strip_comments: bool,
+
+    /// Serve `¦scss` and `¦stylus` sections as written instead of compiling
+    /// them, e.g. to tell whether a styling bug comes from the compiler. This
+    /// turns off all styling preprocessing.
+    #[arg(long)]
+    pub no_scss: bool,
+
+    /// Also reload open pages when files matching this glob change (e.g. a
+    /// `data.json` fetched by the script). Can be repeated.
+    #[arg(long = "watch-extra", value_name = "GLOB")]
+    pub watch_extra: Vec<String>,
+
+    /// Read the .breach source from standard input instead of a file. The
+    /// content is served as-is, without watching for changes.
+    #[arg(long)]
+    pub stdin: bool,
+
+    /// When FILE is a URL, fetch it again every this many seconds and reload
+    /// open pages when it changed.
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..), requires = "file")]
+    pub poll_remote: Option<u64>,
+
+    /// Keep looking for a .breach file for up to this many seconds before giving
+    /// up, for scripts that create the file while the server starts.
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    pub wait: u64,
+
+    /// Don't start the HTTP server; recompile on every change and write the
+    /// outputs to `--out-dir` (or stdout when not set).
+    #[arg(long)]
+    pub no_serve: bool,
+
+    /// Directory receiving `index.html`, `style.css` and `script.js` in
+    /// `--no-serve` mode.
+    #[arg(long, value_name = "DIR")]
+    pub out_dir: Option<PathBuf>,
+
+    /// While serving, also write `index.html`, `style.css` and `script.js` to
+    /// this directory on every reload, without the livereload client.
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["no_serve", "once"])]
+    pub emit: Option<PathBuf>,
+
+    /// Keep running when the .breach file fails to load at startup: serve the
+    /// error in the overlay and watch the file, so fixing it recovers without a
+    /// restart. Without it, a startup failure exits.
+    #[arg(long, conflicts_with_all = ["no_serve", "once"])]
+    pub serve_on_error: bool,
+
+    /// Shut down after this many seconds without HTTP requests and without open
+    /// pages connected for live reload, e.g. for previews launched by tooling.
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["no_serve", "once"])]
+    pub timeout: Option<u64>,
+
+    /// Write `--out-dir` and `--emit` files exactly as compiled instead of
+    /// ending each with a single newline.
+    #[arg(long)]
+    pub no_final_newline: bool,
+
+    /// Drop `sourceMappingURL` comments from written CSS and JS files.
+    #[arg(long)]
+    pub strip_source_maps: bool,
+
+    /// Remove the indentation shared by all lines of each written file.
+    #[arg(long)]
+    pub dedent: bool,
+
+    /// Shared directory searched by SCSS `@use`/`@import` and by `¦include`
+    /// paths not found next to the including file, e.g. for mixins or helpers
+    /// used by several .breach files. Watched for changes. Can be repeated.
+    #[arg(long = "include-dir", value_name = "DIR")]
+    pub include_dirs: Vec<PathBuf>,
+
+    /// Keep the prepared outputs in a `.breach-cache` file next to the source and
+    /// serve them on the next start while the source is unchanged, so large SCSS
+    /// projects don't recompile on every restart.
+    #[arg(long, overrides_with = "no_cache")]
+    pub cache: bool,
+
+    /// Don't read or write the cache (the default; overrides an earlier `--cache`).
+    #[arg(long, overrides_with = "cache")]
+    pub no_cache: bool,
+
+    /// Refuse to load source files larger than this many bytes.
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_FILE_SIZE)]
+    pub max_file_size: u64,
+
+    /// Shell command to run whenever the content changed, before open pages
+    /// reload. Gets the .breach path as `$1` and in `BREACH_PATH`.
+    #[arg(long, value_name = "CMD")]
+    pub on_reload: Option<String>,
+
+    /// Serve the page for any GET path that doesn't look like a file (e.g.
+    /// `/about`), for apps with client-side routing.
+    #[arg(long)]
+    pub spa: bool,
+
+    /// Serve a static file whose name differs from the request path only in case
+    /// (`/Logo.png` for `logo.png`) when no file matches exactly, as on the
+    /// case-insensitive filesystems of macOS and Windows.
+    #[arg(long)]
+    pub ignore_case: bool,
+
+    /// Link the stylesheets and script by content-hashed names
+    /// (`/style.<hash>.css`) served with `Cache-Control: immutable`, instead of
+    /// `?v=` URLs. `--out-dir` files get the hashed names too.
+    #[arg(long)]
+    pub hashed_assets: bool,
+
+    /// Redirect between the two URLs of the page: `root` sends `/index.html` to
+    /// the canonical `/`, `index` sends `/` to `/index.html`, `none` serves both.
+    #[arg(long, value_name = "MODE", default_value = "none")]
+    pub index_redirect: IndexRedirect,
+
+    /// Number of pending reload notifications kept per connected page. A page
+    /// that falls further behind (many rapid edits, a stalled tab) skips the
+    /// missed notifications and reloads once.
+    #[arg(long, value_name = "N", default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    pub reload_capacity: u64,
+
+    /// Number of HTTP worker threads, e.g. to match a container's CPU limit.
+    /// Defaults to one per CPU core, like ntex.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    pub workers: Option<u16>,
+
+    /// Don't add the default security headers (`X-Content-Type-Options: nosniff`)
+    /// to responses, e.g. to test content sniffing.
+    #[arg(long)]
+    pub no_security_headers: bool,
+
+    /// Add a header to every response, as `Name: value`. Can be repeated.
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = parse_header)]
+    pub headers: Vec<(String, String)>,
+
+    /// Require HTTP basic auth (`USER:PASS`) for every request, including the
+    /// livereload WebSocket. Meant for `--public` or other non-local binds.
+    #[arg(long, value_name = "USER:PASS", value_parser = parse_credentials)]
+    pub auth: Option<(String, String)>,
+
+    /// Let connections from localhost through without `--auth` credentials.
+    #[arg(long, requires = "auth")]
+    pub auth_skip_localhost: bool,
+
+    /// After each reload, log a short diff of every changed section, and say so
+    /// when a change on disk didn't change the output, to find out why pages
+    /// did or didn't reload.
+    #[arg(long)]
+    pub reload_diff: bool,
+
+    /// Time the read, parse, compile, injection and hashing phases of every
+    /// reload, and print their min/avg/max on shutdown next to the cold start.
+    #[arg(long, conflicts_with_all = ["once", "show_config", "no_serve", "stdin"])]
+    pub profile: bool,
+
+    /// Log format: `text`, colored only on a terminal and unless `NO_COLOR` is
+    /// set, or `json` for one structured object per line.
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Enable development endpoints under `/__breach/` (e.g. `POST /__breach/reload`).
+    #[arg(long)]
+    pub dev: bool,
+}
+
+/// Parses a `Name: value` response header argument.
+fn parse_header(arg: &str) -> Result<(String, String), String> {
+    let (name, value) = arg
+        .split_once(':')
+        .ok_or_else(|| format!("expected `Name: value`, got '{}'", arg))?;
+    let (name, value) = (name.trim(), value.trim());
+    HeaderName::from_bytes(name.as_bytes()).map_err(|e| format!("invalid header name '{}': {}", name, e))?;
+    HeaderValue::from_str(value).map_err(|e| format!("invalid value for header '{}': {}", name, e))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// How log lines are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines, colored on a terminal.
+    #[default]
+    Text,
+    /// One JSON object per line, for CI and log collectors.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format '{}' (expected 'text' or 'json')", other)),
+        }
+    }
+}
+
+/// Subcommands run instead of the server.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Print every line of a .breach file with the section the parser assigns it
+    /// to, flagging dropped lines and markers that are not recognized.
+    Doctor {
+        /// The .breach file to check, or a directory to look for one in. Found
+        /// like the served file when omitted.
+        #[arg(value_name = "FILE|DIR")]
+        file: Option<PathBuf>,
+    },
+}
+
+/// Parses an `--auth` value of the form `user:pass`.
+fn parse_credentials(arg: &str) -> Result<(String, String), String> {
+    let (user, pass) = arg
+        .split_once(':')
+        .ok_or_else(|| "expected `USER:PASS`".to_string())?;
+    if user.is_empty() {
+        return Err("the user name must not be empty".to_string());
+    }
+    Ok((user.to_string(), pass.to_string()))
+}
+
+/// Parses a `--reload-message` value, refusing text the livereload client
+/// would read as another message.
+fn parse_reload_message(arg: &str) -> Result<String, String> {
+    if arg.is_empty() || arg.starts_with("hello ") || arg.starts_with('{') {
+        return Err("must not be empty, start with `hello ` or start with `{`".to_string());
+    }
+    Ok(arg.to_string())
+}
+
+impl Cli {
+    /// Builds the options used when preparing content for serving.
+    pub fn prepare_options(&self) -> PrepareOptions {
+        PrepareOptions {
+            reload_mode: self.reload_mode,
+            reload_target: self.reload_target,
+            reconnect_attempts: self.reconnect_attempts,
+            reload_message: self.reload_message.clone(),
+            reload_json: self.reload_json,
+            style_fallback: self.style_fallback,
+            strict: self.strict,
+            strip_comments: self.strip_comments,
+            raw_styling: self.no_scss,
+            livereload: !self.no_serve && self.once.is_none(),
+            max_file_size: self.max_file_size,
+            hashed_assets: self.hashed_assets,
+            include_dirs: self.include_dirs.clone(),
+        }
+    }
+
+    /// Headers added to every response: the security defaults (unless disabled)
+    /// followed by any `--header` values.
+    pub fn response_headers(&self) -> Vec<(String, String)> {
+        let defaults = if self.no_security_headers { &[][..] } else { SECURITY_HEADERS };
+        defaults
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .chain(self.headers.iter().cloned())
+            .collect()
+    }
+
+    /// How `--out-dir` and `--emit` files are formatted.
+    pub fn write_format(&self) -> WriteFormat {
+        WriteFormat {
+            final_newline: !self.no_final_newline,
+            source_map_comments: !self.strip_source_maps,
+            dedent: self.dedent,
+        }
+    }
+
+    /// The URL to fetch the content from, when FILE is one.
+    pub fn remote_url(&self) -> Option<&str> {
+        self.file.as_deref()?.to_str().filter(|file| fetch::is_url(file))
+    }
+
+    /// Whether `--cache` is in effect; the later of `--cache` and `--no-cache` wins.
+    pub fn use_cache(&self) -> bool {
+        self.cache && !self.no_cache
+    }
+
+    /// Whether compiled output goes to stdout, in which case logs must not.
+    pub fn prints_to_stdout(&self) -> bool {
+        self.command.is_some() || self.once.is_some() || self.show_config || (self.no_serve && self.out_dir.is_none())
+    }
+
+    /// Describes the settings in effect for content with `config`, one per line
+    /// as `name  value  source`. The source is the command line flag, the line of
+    /// the .breach file, or `default`.
+    pub fn describe_config(&self, config: &BreachConfig) -> String {
+        let mut settings: Vec<(&str, String, String)> = Vec::new();
+
+        // Settings of the .breach file; `--strip-comments` and `¦js module` win over them
+        let from_file = |key: &str, fallback: &str| match config.lines.get(key) {
+            Some(line) => format!("line {}", line),
+            None => fallback.to_string(),
+        };
+        let title = config.title.clone().unwrap_or_else(|| "(from markup)".to_string());
+        settings.push(("title", title, from_file("title", "default")));
+        let (strip_comments, source) = if self.strip_comments {
+            (true, "--strip-comments".to_string())
+        } else {
+            (config.strip_comments, from_file("strip_comments", "default"))
+        };
+        settings.push(("strip_comments", strip_comments.to_string(), source));
+        let (script_type, fallback) = match config.script_type {
+            ScriptType::Classic => ("classic", "default"),
+            ScriptType::Module => ("module", "¦js module"),
+        };
+        settings.push(("script_type", script_type.to_string(), from_file("script_type", fallback)));
+        let viewport = config.viewport.content().unwrap_or("off").to_string();
+        settings.push(("viewport", viewport, from_file("viewport", "default")));
+
+        // Command line settings, attributed to their flag when not at the default
+        let mut push = |name, value: String, set: bool, flag: &str| {
+            let source = if set { flag } else { "default" };
+            settings.push((name, value, source.to_string()));
+        };
+        let list = |items: Vec<String>| if items.is_empty() { "(none)".to_string() } else { items.join(", ") };
+        if self.public {
+            push("host", "0.0.0.0".to_string(), true, "--public");
+        } else {
+            push("host", self.host.clone(), self.host != "localhost", "--host");
+        }
+        let port = match self.port_auto {
+            Some(tries) => format!("{} (or up to {} following)", self.port, tries),
+            None => self.port.to_string(),
+        };
+        push("port", port, self.port != DEFAULT_PORT || self.port_auto.is_some(), "--port");
+        let reload_mode = self.reload_mode;
+        push("reload_mode", reload_mode.as_str().to_string(), reload_mode != ReloadMode::Auto, "--reload-mode");
+        let reload_target = self.reload_target;
+        push("reload_target", reload_target.as_str().to_string(), reload_target != ReloadTarget::Window, "--reload-target");
+        let reconnect_attempts = self.reconnect_attempts;
+        let custom = reconnect_attempts != DEFAULT_RECONNECT_ATTEMPTS;
+        push("reconnect_attempts", reconnect_attempts.to_string(), custom, "--reconnect-attempts");
+        if self.reload_json {
+            push("reload_message", "(json)".to_string(), true, "--reload-json");
+        } else {
+            let custom = self.reload_message != DEFAULT_RELOAD_MESSAGE;
+            push("reload_message", self.reload_message.clone(), custom, "--reload-message");
+        }
+        push("style_fallback", self.style_fallback.to_string(), self.style_fallback, "--style-fallback");
+        push("strict", self.strict.to_string(), self.strict, "--strict");
+        push("hashed_assets", self.hashed_assets.to_string(), self.hashed_assets, "--hashed-assets");
+        push("spa", self.spa.to_string(), self.spa, "--spa");
+        push("ignore_case", self.ignore_case.to_string(), self.ignore_case, "--ignore-case");
+        let index_redirect = format!("{:?}", self.index_redirect).to_ascii_lowercase();
+        let redirects = self.index_redirect != IndexRedirect::None;
+        push("index_redirect", index_redirect, redirects, "--index-redirect");
+        let max_file_size = self.max_file_size;
+        push("max_file_size", max_file_size.to_string(), max_file_size != DEFAULT_MAX_FILE_SIZE, "--max-file-size");
+        let include_dirs = self.include_dirs.iter().map(|dir| dir.display().to_string()).collect();
+        push("include_dirs", list(include_dirs), !self.include_dirs.is_empty(), "--include-dir");
+        push("watch_extra", list(self.watch_extra.clone()), !self.watch_extra.is_empty(), "--watch-extra");
+        push("cache", self.use_cache().to_string(), self.use_cache(), "--cache");
+        let security_headers = !self.no_security_headers;
+        push("security_headers", security_headers.to_string(), !security_headers, "--no-security-headers");
+        let headers = self.headers.iter().map(|(name, _)| name.clone()).collect();
+        push("headers", list(headers), !self.headers.is_empty(), "--header");
+        let auth = match &self.auth {
+            Some((user, _)) => format!("user '{}'", user),
+            None => "off".to_string(),
+        };
+        push("auth", auth, self.auth.is_some(), "--auth");
+        let timeout = self.timeout.map_or("off".to_string(), |secs| format!("{}s", secs));
+        push("timeout", timeout, self.timeout.is_some(), "--timeout");
+        let workers = self.workers.map_or("one per core".to_string(), |n| n.to_string());
+        push("workers", workers, self.workers.is_some(), "--workers");
+        push("no_scss", self.no_scss.to_string(), self.no_scss, "--no-scss");
+        push("reload_diff", self.reload_diff.to_string(), self.reload_diff, "--reload-diff");
+        push("dev", self.dev.to_string(), self.dev, "--dev");
+
+        let width = settings.iter().map(|(_, value, _)| value.chars().count()).max().unwrap_or(0);
+        settings
+            .iter()
+            .map(|(name, value, source)| format!("{:<16}  {:<width$}  {}\n", name, value, source, width = width))
+            .collect()
+    }
+
+    /// Resolves `--host` into the socket addresses the server should bind.
+    pub fn bind_addrs(&self, port: u16) -> Result<Vec<SocketAddr>, String> {
+        let host = if self.public { "0.0.0.0" } else { self.host.as_str() };
+        match host {
+            "localhost" => Ok(vec![
+                (Ipv4Addr::LOCALHOST, port).into(),
+                (Ipv6Addr::LOCALHOST, port).into(),
+            ]),
+            "0.0.0.0" | "::" | "[::]" => Ok(vec![
+                (Ipv4Addr::UNSPECIFIED, port).into(),
+                (Ipv6Addr::UNSPECIFIED, port).into(),
+            ]),
+            other => other
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .map(|ip| vec![SocketAddr::new(ip, port)])
+                .map_err(|e| format!("Invalid --host '{}': {}", other, e)),
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use fxhash::FxHashMap;
+
+/// Settings declared in the `¦config` section of a .breach file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BreachConfig {
+    /// Page title used when the markup does not declare one.
+    pub title: Option<String>,
+    /// Remove regular comments from compiled SCSS (see `--strip-comments`).
+    pub strip_comments: bool,
+    /// How the script is loaded by the page.
+    pub script_type: ScriptType,
+    /// The viewport meta tag added to pages that don't declare one.
+    pub viewport: Viewport,
+    /// Files copied next to the written outputs besides those the page links,
+    /// as paths or globs relative to the source file.
+    pub assets: Vec<String>,
+    /// Line of the source file each key was taken from; keys left at their
+    /// default are absent.
+    pub lines: FxHashMap<String, usize>,
+}
+
+/// Viewport meta tag injected when the page has none, so layouts render at
+/// device width on phones.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Viewport {
+    /// `width=device-width, initial-scale=1`.
+    #[default]
+    Default,
+    /// Inject nothing.
+    Off,
+    /// Inject this `content` value.
+    Content(String),
+}
+
+impl Viewport {
+    /// The `content` of the tag to inject, if any.
+    pub fn content(&self) -> Option<&str> {
+        match self {
+            Viewport::Default => Some("width=device-width, initial-scale=1"),
+            Viewport::Off => None,
+            Viewport::Content(content) => Some(content),
+        }
+    }
+}
+
+/// How the injected `/script.js` tag loads the script.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScriptType {
+    /// A classic script sharing the global scope.
+    #[default]
+    Classic,
+    /// A native ES module (`<script type="module">`), so `import`/`export` work.
+    Module,
+}
+
+impl std::str::FromStr for ScriptType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "classic" | "script" => Ok(ScriptType::Classic),
+            "module" | "esm" => Ok(ScriptType::Module),
+            other => Err(format!("unknown script type '{}' (expected 'classic' or 'module')", other)),
+        }
+    }
+}
+
+impl BreachConfig {
+    /// Applies a single `key = value` setting, returning whether it was applied.
+    /// Unknown keys and invalid values are logged and ignored.
+    fn set(&mut self, key: &str, value: String) -> bool {
+        match key {
+            "title" => self.title = Some(value),
+            "strip_comments" => match parse_bool(&value) {
+                Some(strip) => self.strip_comments = strip,
+                None => {
+                    tracing::warn!("Config key 'strip_comments' expects true or false, got '{}'", value);
+                    return false;
+                }
+            },
+            "viewport" => {
+                self.viewport = match parse_bool(&value) {
+                    Some(true) => Viewport::Default,
+                    Some(false) => Viewport::Off,
+                    None => Viewport::Content(value),
+                }
+            }
+            "assets" => {
+                self.assets = value
+                    .split(',')
+                    .map(|pattern| unquote(pattern.trim()).to_string())
+                    .filter(|pattern| !pattern.is_empty())
+                    .collect()
+            }
+            "script_type" => match value.parse() {
+                Ok(script_type) => self.script_type = script_type,
+                Err(e) => {
+                    tracing::warn!("Config key 'script_type': {}", e);
+                    return false;
+                }
+            },
+            other => {
+                tracing::warn!("Unknown config key '{}' ignored", other);
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Parses a boolean setting value.
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Strips one pair of matching single or double quotes around a value.
+pub(crate) fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+/// Splits a leading front-matter block delimited by `+++` or `---` lines off
+/// `source`, returning its contents and the rest of the source. The delimiters
+/// are those of TOML and YAML front-matter, but the contents are the flat
+/// `key = value` lines of `¦config` (see [`parse_config`]), not TOML or YAML.
+/// An unterminated block is not treated as front-matter.
+pub fn split_front_matter(source: &str) -> (Option<&str>, &str) {
+    let Some((first, rest)) = source.split_once('\n') else {
+        return (None, source);
+    };
+    let delimiter = first.trim_end();
+    if delimiter != "+++" && delimiter != "---" {
+        return (None, source);
+    }
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            let body = &rest[offset + line.len()..];
+            return (Some(&rest[..offset]), body);
+        }
+        offset += line.len();
+    }
+    tracing::warn!("Front-matter opened with '{}' is never closed; treating it as content", delimiter);
+    (None, source)
+}
+
+/// Parses config lines of the form `key = value` (or `key: value`), each paired
+/// with its line number in the source file. Values may be quoted; blank lines and
+/// lines starting with `#` are ignored. A key set more than once keeps its last
+/// value, with a warning naming both lines.
+pub fn parse_config<'a>(lines: impl IntoIterator<Item = (usize, &'a str)>) -> BreachConfig {
+    let mut config = BreachConfig::default();
+    let mut seen: FxHashMap<String, usize> = FxHashMap::default();
+    for (line_no, line) in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some(split_at) = line.find(['=', ':']) else {
+            tracing::warn!("Ignoring config line {} without a value: {}", line_no, line);
+            continue;
+        };
+        let key = line[..split_at].trim();
+        let value = unquote(line[split_at + 1..].trim());
+        if let Some(first) = seen.insert(key.to_string(), line_no) {
+            tracing::warn!(
+                "Config key '{}' on line {} overrides the value set on line {}",
+                key,
+                line_no,
+                first
+            );
+        }
+        if config.set(key, value.to_string()) {
+            config.lines.insert(key.to_string(), line_no);
+        }
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> BreachConfig {
+        parse_config(source.lines().enumerate().map(|(i, line)| (i + 1, line)))
+    }
+
+    #[test]
+    fn front_matter_is_split_off_the_source() {
+        let (front_matter, rest) = split_front_matter("+++\ntitle = \"A\"\n+++\n¦html\n<p></p>\n");
+        assert_eq!(front_matter, Some("title = \"A\"\n"));
+        assert_eq!(rest, "¦html\n<p></p>\n");
+        let (front_matter, rest) = split_front_matter("---\ntitle: A\n---\n¦html\n");
+        assert_eq!(front_matter, Some("title: A\n"));
+        assert_eq!(rest, "¦html\n");
+    }
+
+    #[test]
+    fn source_without_front_matter_is_unchanged() {
+        let source = "¦html\n+++\n<p></p>\n+++\n";
+        assert_eq!(split_front_matter(source), (None, source));
+        assert_eq!(split_front_matter(""), (None, ""));
+    }
+
+    #[test]
+    fn unterminated_or_mismatched_front_matter_is_content() {
+        let source = "+++\ntitle = \"A\"\n¦html\n";
+        assert_eq!(split_front_matter(source), (None, source));
+        let source = "+++\ntitle = \"A\"\n---\n¦html\n";
+        assert_eq!(split_front_matter(source), (None, source));
+    }
+
+    #[test]
+    fn key_value_lines_accept_both_separators_and_quotes() {
+        let config = parse("# a comment\n\ntitle: 'Hello: world'\nstrip_comments = yes\nscript_type = \"module\"\n");
+        assert_eq!(config.title.as_deref(), Some("Hello: world"));
+        assert!(config.strip_comments);
+        assert_eq!(config.script_type, ScriptType::Module);
+        assert_eq!(config.lines.get("title"), Some(&3));
+        assert_eq!(config.lines.get("script_type"), Some(&5));
+    }
+
+    #[test]
+    fn later_lines_override_earlier_ones() {
+        let config = parse("title = A\ntitle = B\n");
+        assert_eq!(config.title.as_deref(), Some("B"));
+        assert_eq!(config.lines.get("title"), Some(&2));
+    }
+
+    #[test]
+    fn invalid_and_unknown_settings_are_ignored() {
+        let config = parse("strip_comments = maybe\nscript_type = wasm\ncolour = red\n[table]\n");
+        assert_eq!(config, BreachConfig::default());
+    }
+
+    #[test]
+    fn values_keep_their_own_separators() {
+        let config = parse("viewport = width=device-width\nassets = a.png, 'b c.svg', ,img/*.jpg\n");
+        assert_eq!(config.viewport, Viewport::Content("width=device-width".to_string()));
+        assert_eq!(config.assets, ["a.png", "b c.svg", "img/*.jpg"]);
+        assert_eq!(parse("viewport = off\n").viewport, Viewport::Off);
+    }
+}
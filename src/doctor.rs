@@ -0,0 +1,186 @@
+use crate::config;
+use crate::error::BreachError;
+use crate::manifest;
+use crate::parser::{self, SectionType};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Names accepted after `¦`, used to spot markers the parser will not recognize.
+const KNOWN_MARKERS: &[&str] = &["html", "head", "css", "scss", "stylus", "js", "javascript", "ts", "typescript", "config", "json", "data", "include", "end"];
+
+/// Lines longer than this many characters (e.g. minified CSS or JS) are cut in
+/// the report, with the number of characters left out.
+const MAX_SHOWN_CHARS: usize = 120;
+
+/// Shortens `line` to [`MAX_SHOWN_CHARS`] characters for display.
+fn shown(line: &str) -> String {
+    match line.char_indices().nth(MAX_SHOWN_CHARS) {
+        Some((cut, _)) => format!("{}… (+{} chars)", &line[..cut], line[cut..].chars().count()),
+        None => line.to_string(),
+    }
+}
+
+/// Explains why a line that looks like a marker is not one, if it looks like one.
+fn marker_hint(line: &str) -> Option<String> {
+    let trimmed = parser::trim_marker_prefix(line);
+    if let Some((ident, _)) = parser::split_marker(line) {
+        if ident.is_empty() {
+            return Some("`¦` without a section name".to_string());
+        }
+        if !KNOWN_MARKERS.iter().any(|known| ident.eq_ignore_ascii_case(known)) {
+            return Some(format!("unknown section `¦{}`", ident));
+        }
+        return None;
+    }
+    if let Some(rest) = trimmed.strip_prefix('¦') {
+        return Some(format!(
+            "not a marker: `¦{}` must be followed by the end of the line, `:` or whitespace",
+            rest.split_whitespace().next().unwrap_or_default()
+        ));
+    }
+    for wrong in ['|', '¬', '¤'] {
+        if let Some(rest) = trimmed.strip_prefix(wrong) {
+            if parser::marker_section(&format!("¦{}", rest)).is_some() {
+                return Some(format!("uses `{}` (U+{:04X}) instead of `¦` (U+00A6)", wrong, wrong as u32));
+            }
+        }
+    }
+    // Only the start of the line matters; this keeps huge minified lines cheap
+    let head = &line[..line.char_indices().nth(MAX_SHOWN_CHARS).map_or(line.len(), |(i, _)| i)];
+    let (before, after) = head.split_once('¦')?;
+    if !before.trim().is_empty() && parser::marker_section(&format!("¦{}", after)).is_some() {
+        return Some("a marker must start the line; this one follows other text".to_string());
+    }
+    None
+}
+
+/// Builds the `doctor` report for `source`: every line with its number and the
+/// section the parser assigns it to. Marker lines are flagged with `¦`, dropped
+/// lines (outside any section) with `!`, and lines that look like a broken
+/// marker get a hint below them. The summary ends with each section's byte range
+/// and the size of what the parser keeps of the markup, styling and script.
+pub fn report(source: &str) -> String {
+    let normalized = parser::normalize_newlines(source.trim_start_matches('\u{feff}'));
+    let (front_matter, body) = config::split_front_matter(&normalized);
+    let body_start = normalized[..normalized.len() - body.len()].lines().count();
+
+    let mut out = String::new();
+    let mut counts: Vec<(SectionType, usize)> = Vec::new();
+    let mut dropped = 0;
+    let mut hints = 0;
+
+    if let Some(front_matter) = front_matter {
+        for (index, line) in normalized.lines().take(body_start).enumerate() {
+            let label = if index == 0 || index + 1 == body_start { "+++" } else { "config" };
+            let _ = writeln!(out, "{:>5}  {:<7}{}", index + 1, label, shown(line));
+        }
+        let entries = front_matter.lines().filter(|line| !line.trim().is_empty()).count();
+        counts.push((SectionType::Config, entries));
+    }
+
+    let mut cur = SectionType::None;
+    for (index, line) in body.lines().enumerate() {
+        let (flag, section) = match parser::marker_section(line) {
+            Some(section) => {
+                cur = section;
+                ('¦', section)
+            }
+            None if cur == SectionType::None && !line.trim().is_empty() => {
+                dropped += 1;
+                ('!', cur)
+            }
+            None => (' ', cur),
+        };
+        if flag == ' ' {
+            match counts.iter_mut().find(|(s, _)| *s == section) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((section, 1)),
+            }
+        }
+        let _ = writeln!(out, "{:>5}{} {:<7}{}", body_start + index + 1, flag, section.name(), shown(line));
+        if let Some(hint) = marker_hint(line) {
+            hints += 1;
+            let _ = writeln!(out, "{:>5}  ^ {}", "", hint);
+        }
+    }
+
+    let summary = counts
+        .iter()
+        .filter(|(section, _)| *section != SectionType::None)
+        .map(|(section, n)| format!("{} {}", section.name(), n))
+        .collect::<Vec<_>>();
+    let _ = writeln!(
+        out,
+        "\nLines per section: {}; {} dropped, {} hint(s)",
+        if summary.is_empty() { "none".to_string() } else { summary.join(", ") },
+        dropped,
+        hints
+    );
+    let (parsed, spans) = parser::parse_breach_content_with_spans(source);
+    let spans = spans
+        .iter()
+        .map(|span| format!("{} {}..{}", span.kind.name(), span.range.start, span.range.end))
+        .collect::<Vec<_>>();
+    if !spans.is_empty() {
+        let _ = writeln!(out, "Section bytes: {}", spans.join(", "));
+    }
+    let size = |section: &Option<String>| section.as_ref().map_or(0, String::len);
+    let _ = writeln!(
+        out,
+        "Parsed: markup {} bytes, styling {} bytes, script {} bytes",
+        size(&parsed.markup),
+        size(&parsed.styling),
+        size(&parsed.script)
+    );
+    out
+}
+
+/// Reads `path` and prints its `doctor` report to stdout. A `breach.toml`
+/// manifest is reported as the source assembled from its files.
+pub fn run(path: &Path, max_file_size: u64, include_dirs: &[PathBuf]) -> Result<(), BreachError> {
+    let source = if manifest::is_manifest(path) {
+        manifest::assemble(path, max_file_size, include_dirs, &mut Vec::new())?
+    } else {
+        let bytes = parser::read_source_file(path, max_file_size)?;
+        parser::decode_source(&bytes)
+    };
+    print!("{}", report(&source));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_lines_are_shortened_for_display() {
+        assert_eq!(shown("short"), "short");
+        let exact = "a".repeat(MAX_SHOWN_CHARS);
+        assert_eq!(shown(&exact), exact);
+        let long = format!("{}{}", "é".repeat(MAX_SHOWN_CHARS), "x".repeat(5));
+        assert_eq!(shown(&long), format!("{}… (+5 chars)", "é".repeat(MAX_SHOWN_CHARS)));
+    }
+
+    #[test]
+    fn report_cuts_a_minified_line() {
+        let script = "var a=1;".repeat(256 * 1024);
+        let out = report(&format!("¦js\n{}\n", script));
+        let line = out.lines().find(|line| line.contains("var a=1;")).unwrap();
+        assert!(line.len() < 200);
+        assert!(line.ends_with(&format!("(+{} chars)", script.len() - MAX_SHOWN_CHARS)));
+    }
+
+    #[test]
+    fn marker_after_text_on_a_long_line_is_not_hinted_past_the_cut() {
+        let line = format!("{} ¦css", "x".repeat(MAX_SHOWN_CHARS));
+        assert_eq!(marker_hint(&line), None);
+        assert!(marker_hint("text ¦css").is_some());
+    }
+
+    #[test]
+    fn summary_lists_section_bytes_and_parsed_sizes() {
+        let out = report("¦html\n<p>a</p>\n¦js\nlet a;\n");
+        assert!(out.contains("Section bytes: html 0..16, script 16..28\n"), "{}", out);
+        assert!(out.ends_with("Parsed: markup 8 bytes, styling 0 bytes, script 6 bytes\n"), "{}", out);
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors produced while loading and preparing breach content.
+#[derive(Debug, Error)]
+pub enum BreachError {
+    /// The source could not be read.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The source exceeds the configured maximum size.
+    #[error("{path:?} is {size} bytes, larger than the {limit} byte limit (see --max-file-size)")]
+    FileTooLarge { path: PathBuf, size: u64, limit: u64 },
+    /// A file named by an `¦include` directive could not be read.
+    #[error("cannot include {path:?}: {source}")]
+    Include {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A file listed in a `breach.toml` manifest could not be read.
+    #[error("cannot read {path:?} listed in the manifest: {source}")]
+    ManifestFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A `breach.toml` manifest uses syntax it can't be read with.
+    #[error("{path:?} line {line}: {message}")]
+    Manifest {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// Content named by a URL could not be downloaded.
+    #[error("cannot fetch {url}: {message}")]
+    Fetch { url: String, message: String },
+    /// Content fetched from a URL uses `¦include`, which would read files on the
+    /// serving machine.
+    #[error("{0} uses ¦include, which is not allowed in fetched content")]
+    RemoteInclude(String),
+    /// An `¦include` directive (directly or indirectly) includes itself.
+    #[error("{0:?} includes itself")]
+    IncludeCycle(PathBuf),
+    /// A SCSS section failed to compile.
+    #[error(transparent)]
+    ScssCompile(#[from] ScssError),
+    /// A Stylus section failed to compile.
+    #[error(transparent)]
+    StylusCompile(#[from] StylusError),
+    /// One or more sections failed to compile while `--strict` was set.
+    #[error("compilation failed:\n{0}")]
+    Compile(String),
+}
+
+/// Error returned when a SCSS section cannot be compiled.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ScssError {
+    /// The SCSS is valid but uses a Dart Sass feature grass does not implement.
+    #[error("SCSS uses a feature the grass compiler does not support yet: {0}")]
+    Unsupported(String),
+    /// The SCSS could not be parsed or evaluated.
+    #[error("SCSS syntax error: {0}")]
+    Syntax(String),
+    /// The compiler panicked on this input instead of reporting an error.
+    #[error("SCSS compiler crashed: {0}")]
+    Panic(String),
+}
+
+impl ScssError {
+    /// Classifies a grass error message. grass reports missing features through
+    /// the same error type as syntax errors, so this relies on its wording.
+    pub fn from_message(message: String) -> Self {
+        let lower = message.to_ascii_lowercase();
+        let unsupported = ["not yet implemented", "unimplemented", "not supported", "unsupported"]
+            .iter()
+            .any(|needle| lower.contains(needle));
+        if unsupported {
+            ScssError::Unsupported(message)
+        } else {
+            ScssError::Syntax(message)
+        }
+    }
+}
+
+/// Error returned when a Stylus section cannot be compiled.
+#[derive(Debug, Error)]
+pub enum StylusError {
+    /// The `stylus` executable could not be started (usually not installed).
+    #[error("Stylus compiler not available ({0}); install it with `npm install -g stylus`")]
+    NotInstalled(std::io::Error),
+    /// The compiler ran but rejected the input.
+    #[error("Stylus compilation failed: {0}")]
+    Failed(String),
+}
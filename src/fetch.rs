@@ -0,0 +1,103 @@
+use crate::error::BreachError;
+use crate::parser::{self, PrepareOptions, PreparedContent};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+/// Returns true if `source` names an HTTP(S) URL rather than a local path.
+pub fn is_url(source: &str) -> bool {
+    let lower = source.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Downloads breach content from `url` by running `curl`, which takes care of
+/// HTTPS and redirects, refusing responses larger than `max_size` bytes. `curl`
+/// must be on the `PATH`; without it the fetch fails saying so.
+fn fetch_url(url: &str, max_size: u64) -> Result<Vec<u8>, BreachError> {
+    let fetch_error = |message: String| BreachError::Fetch { url: url.to_string(), message };
+    let mut child = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location", "--max-time", "30"])
+        .arg(url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| fetch_error(format!("curl is needed to fetch URLs ({})", e)))?;
+
+    let stdout = child.stdout.take().expect("curl stdout is piped");
+    let bytes = match parser::read_limited(stdout, Path::new(url), max_size) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+    };
+    let output = child.wait_with_output().map_err(|e| fetch_error(e.to_string()))?;
+    if !output.status.success() {
+        return Err(fetch_error(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(bytes)
+}
+
+/// Fetches, parses and prepares the content at `url`. Nothing is watched;
+/// `--poll-remote` calls this again to pick up changes. `¦include` is refused.
+pub fn load_prepared(
+    url: &str,
+    options: &PrepareOptions,
+    previous: Option<&PreparedContent>,
+) -> Result<PreparedContent, BreachError> {
+    let started = Instant::now();
+    let bytes = fetch_url(url, options.max_file_size)?;
+    let mut prepared = prepare_fetched(url, &bytes, options, previous)?;
+    prepared.timings.read += started.elapsed();
+    Ok(prepared)
+}
+
+/// Parses and prepares `bytes` fetched from `url`, refusing `¦include`.
+fn prepare_fetched(
+    url: &str,
+    bytes: &[u8],
+    options: &PrepareOptions,
+    previous: Option<&PreparedContent>,
+) -> Result<PreparedContent, BreachError> {
+    let started = Instant::now();
+    let source = parser::decode_source(bytes);
+    // Includes resolve against the local filesystem, which a remote document
+    // must not be able to read and publish
+    if source.lines().any(|line| parser::starts_with_section_marker(line, "include")) {
+        return Err(BreachError::RemoteInclude(url.to_string()));
+    }
+    let read = started.elapsed();
+    let started = Instant::now();
+    let parsed = parser::parse_breach_content(&source);
+    let parse = started.elapsed();
+    let mut prepared = parser::prepare(parsed, options, previous);
+    prepared.timings.read = read;
+    prepared.timings.parse = parse;
+    Ok(prepared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_http_and_https_are_urls() {
+        for source in ["http://a.test/x.breach", "HTTPS://a.test/", "https://a.test"] {
+            assert!(is_url(source), "{}", source);
+        }
+        for source in ["index.breach", "./http://x", "ftp://a.test/x.breach", "file:///x.breach"] {
+            assert!(!is_url(source), "{}", source);
+        }
+    }
+
+    #[test]
+    fn fetched_content_may_not_include_files() {
+        let options = PrepareOptions { livereload: false, ..Default::default() };
+        let url = "https://a.test/x.breach";
+        let prepared = prepare_fetched(url, "¦html\n<p>a</p>\n".as_bytes(), &options, None).unwrap();
+        assert_eq!(prepared.parsed.markup.as_deref(), Some("<p>a</p>"));
+        let included = prepare_fetched(url, "¦html\n¦include /etc/passwd\n".as_bytes(), &options, None);
+        assert!(matches!(included, Err(BreachError::RemoteInclude(u)) if u == url));
+    }
+}
@@ -1,66 +1,505 @@
-use std::{error::Error, fs, path::PathBuf, sync::Arc};
+use std::{
+    error::Error,
+    fs,
+    io::{self, IsTerminal},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicUsize, Arc},
+    thread,
+    time::{Duration, Instant},
+};
 
 use arc_swap::ArcSwap;
+use clap::Parser;
+use socket2::{Domain, Protocol, Socket, Type};
 use ntex::web;
-use tracing::{error, info};
-use tracing_subscriber;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
 
+mod assets;
+mod cache;
+mod cli;
+mod config;
+mod doctor;
+mod error;
+mod fetch;
+mod manifest;
+mod minify;
+mod output;
 mod parser;
+mod report;
+mod scope;
 mod server;
 mod watch;
 
-// Find the first `.breach` file in the current directory
-fn get_breach() -> Result<Option<PathBuf>, Box<dyn Error>> {
-    let paths = fs::read_dir("./")?;
-    for entry in paths {
-        let path = entry?.path();
+// File names picked over other `.breach` files in the directory, in this order
+const PREFERRED_NAMES: &[&str] = &["index.breach", "main.breach"];
+
+// Find the `.breach` file to serve in the current directory: `entry` (from
+// `--entry`) if present, then the preferred names, then the first one by name,
+// and finally a `breach.toml` manifest
+fn get_breach(dir: &Path, entry: Option<&str>) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let mut found = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        let path = dir_entry?.path();
         if path.is_file() && path.extension().map(|e| e == "breach").unwrap_or(false) {
-            return Ok(Some(path));
+            found.push(path);
+        }
+    }
+    found.sort();
+
+    for name in entry.into_iter().chain(PREFERRED_NAMES.iter().copied()) {
+        if let Some(path) = found.iter().find(|p| p.file_name().is_some_and(|f| f == name)) {
+            info!("Using {:?}", path);
+            return Ok(Some(path.clone()));
+        }
+    }
+    if let Some(name) = entry {
+        warn!("No {} found, falling back to the first .breach file", name);
+    }
+    let first = found.first().cloned();
+    if let Some(path) = &first {
+        if found.len() > 1 {
+            info!(
+                "Using {:?}, the first of {} .breach files (pass a file or --entry to choose)",
+                path,
+                found.len()
+            );
         }
+        return Ok(first);
+    }
+
+    // Projects split into separate files are assembled from a manifest instead
+    let manifest = dir.join(manifest::MANIFEST_NAME);
+    if manifest.is_file() {
+        info!("No .breach file, using {:?}", manifest);
+        return Ok(Some(manifest));
     }
     Ok(None)
 }
 
+// Like `get_breach`, but keeps retrying until a file appears or `wait` elapses, for
+// scripts that create the file and start the server at the same time
+fn wait_for_breach(dir: &Path, entry: Option<&str>, wait: Duration) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let deadline = Instant::now() + wait;
+    let mut attempt = 1;
+    loop {
+        if let Some(path) = get_breach(dir, entry)? {
+            return Ok(Some(path));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        debug!("No .breach file found yet (attempt {}), retrying", attempt);
+        attempt += 1;
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+// Bind one address like `TcpListener::bind`, except that IPv6 sockets are
+// IPv6-only. Linux makes `[::]` dual-stack by default, which then takes the IPv4
+// port as well and fails with EADDRINUSE next to `0.0.0.0`
+fn bind_listener(addr: &SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // As std does, so a restart can rebind a port with connections in TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&(*addr).into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
+// Bind every requested address, tolerating individual failures (e.g. no IPv6
+// stack) as long as one succeeds. The first address being in use means another
+// server has the port, which is reported as such instead of settling for the
+// remaining addresses
+fn bind_listeners(addrs: &[SocketAddr]) -> io::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    let mut last_err = None;
+    for (i, addr) in addrs.iter().enumerate() {
+        match bind_listener(addr) {
+            Err(e) if i == 0 && e.kind() == io::ErrorKind::AddrInUse => return Err(e),
+            Ok(listener) => {
+                info!("Listening on http://{}", addr);
+                listeners.push(listener);
+            }
+            Err(e) => {
+                warn!("Could not bind {}: {}", addr, e);
+                last_err = Some(e);
+            }
+        }
+    }
+    if listeners.is_empty() {
+        return Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address to bind")
+        }));
+    }
+    Ok(listeners)
+}
+
+// Bind `--port`, or with `--port-auto` the first free one of the ports after it.
+// Returns the bound addresses and listeners
+fn bind_port(cli: &cli::Cli) -> Result<(Vec<SocketAddr>, Vec<TcpListener>), Box<dyn Error>> {
+    let attempts = cli.port_auto.unwrap_or(0);
+    let mut port = cli.port;
+    for attempt in 0..=attempts {
+        let addrs = cli.bind_addrs(port)?;
+        match bind_listeners(&addrs) {
+            Ok(listeners) => {
+                if port != cli.port {
+                    info!("Port {} is in use, using port {} instead", cli.port, port);
+                }
+                return Ok((addrs, listeners));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                let next = port.checked_add(1).filter(|_| attempt < attempts);
+                match next {
+                    Some(next) => {
+                        debug!("Port {} is in use, trying {}", port, next);
+                        port = next;
+                    }
+                    None if cli.port_auto.is_some() => {
+                        return Err(format!("ports {} to {} are all in use; pick another range with --port", cli.port, port).into());
+                    }
+                    None => {
+                        return Err(format!(
+                            "port {} is already in use (another b-reach running?); pass --port to pick another, or --port-auto to take the next free one",
+                            port
+                        )
+                        .into());
+                    }
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    unreachable!("the last attempt always returns")
+}
+
+// The address other machines on the LAN most likely reach this one at: the local
+// end of a UDP socket "connected" to a public address (no packet is sent)
+fn lan_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 80)).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+}
+
+// Runs `f` on its own thread after every reload notification, until the
+// channel closes
+fn on_every_reload(reload_tx: &broadcast::Sender<()>, mut f: impl FnMut() + Send + 'static) {
+    let mut rx = reload_tx.subscribe();
+    thread::spawn(move || loop {
+        match rx.blocking_recv() {
+            Ok(()) | Err(RecvError::Lagged(_)) => f(),
+            Err(RecvError::Closed) => break,
+        }
+    });
+}
+
 #[ntex::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Initialize tracing with INFO level
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .init();
-
-    let breach_path = match get_breach()? {
-        Some(p) => p,
-        None => {
-            error!("No .breach file found in the current directory.");
-            return Ok(());
+    let cli = cli::Cli::parse();
+
+    // Initialize tracing at the level `RUST_LOG` asks for (INFO by default), on
+    // stderr when stdout carries output. Colors only go to a terminal, so CI logs
+    // and pipes get plain text
+    let to_stderr = cli.prints_to_stdout();
+    let is_terminal = if to_stderr { io::stderr().is_terminal() } else { io::stdout().is_terminal() };
+    let color = cli.log_format == cli::LogFormat::Text && report::color_wanted(is_terminal);
+    report::set_color(color);
+    report::set_diff(cli.reload_diff);
+    report::set_profile(cli.profile);
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_ansi(color);
+    match (cli.log_format, to_stderr) {
+        (cli::LogFormat::Json, true) => subscriber.json().with_writer(io::stderr).init(),
+        (cli::LogFormat::Json, false) => subscriber.json().init(),
+        (cli::LogFormat::Text, true) => subscriber.with_writer(io::stderr).init(),
+        (cli::LogFormat::Text, false) => subscriber.init(),
+    }
+
+    // Everything relative (FILE, --out-dir, --watch-extra, ...) resolves against --cwd
+    if let Some(dir) = &cli.cwd {
+        std::env::set_current_dir(dir).map_err(|e| format!("cannot change to {}: {}", dir.display(), e))?;
+        info!("Working in {:?}", dir);
+    }
+
+    if let Some(cli::Command::Doctor { file }) = &cli.command {
+        let path = match file.as_deref().filter(|f| !f.is_dir()) {
+            Some(file) => file.to_path_buf(),
+            None => {
+                let dir = file.as_deref().unwrap_or(Path::new("."));
+                match get_breach(dir, cli.entry.as_deref())? {
+                    Some(path) => path,
+                    None => {
+                        error!("No .breach file found in {:?}.", dir);
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        doctor::run(&path, cli.max_file_size, &cli.include_dirs)?;
+        return Ok(());
+    }
+
+    let options = cli.prepare_options();
+
+    // Create broadcast channel for live reload notifications
+    let (reload_tx, _) = tokio::sync::broadcast::channel(cli.reload_capacity as usize);
+
+    let (breach_path, prepared) = if cli.stdin {
+        // Piped content has nothing to watch, so it is prepared once and served as-is
+        info!("Reading breach content from stdin");
+        let bytes = parser::read_limited(io::stdin(), Path::new("<stdin>"), options.max_file_size)?;
+        let source = parser::decode_source(&bytes);
+        let mut dependencies = Vec::new();
+        let source = parser::expand_includes(&source, None, options.max_file_size, &options.include_dirs, &mut dependencies)?;
+        let prepared = parser::prepare(parser::parse_breach_content(&source), &options, None);
+        (None, prepared)
+    } else if let Some(url) = cli.remote_url() {
+        // Remote content has nothing to watch; --poll-remote fetches it again instead
+        info!("Fetching breach content from {}", url);
+        let prepared = match fetch::load_prepared(url, &options, None) {
+            Ok(prepared) => prepared,
+            Err(e) if cli.serve_on_error => {
+                error!("{}", e);
+                warn!("Serving the error until the content loads (--serve-on-error)");
+                let parsed = parser::ParsedContent { load_error: Some(e.to_string()), ..Default::default() };
+                parser::prepare(parsed, &options, None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        (None, prepared)
+    } else {
+        // A FILE naming a directory is searched like the working directory
+        let search_dir = cli.file.as_deref().filter(|f| f.is_dir());
+        let found = match &cli.file {
+            Some(file) if search_dir.is_none() => {
+                if cli.entry.is_some() {
+                    warn!("Ignoring --entry, {:?} is served as given", file);
+                }
+                Some(file.clone())
+            }
+            _ => wait_for_breach(
+                search_dir.unwrap_or(Path::new(".")),
+                cli.entry.as_deref(),
+                Duration::from_secs(cli.wait),
+            )?,
+        };
+        let breach_path = match found {
+            Some(p) => p,
+            None => {
+                error!("No .breach file found in {:?}.", search_dir.unwrap_or(Path::new(".")));
+                return Ok(());
+            }
+        };
+
+        info!("Loading breach file: {:?}", breach_path);
+        let cache_path = cli.use_cache().then(|| cache::path_for(&breach_path));
+        let cached = cache_path.as_deref().and_then(cache::load);
+        let prepared = match parser::load_prepared_from_file(&breach_path, &options, cached.as_ref()) {
+            Ok(prepared) => prepared,
+            Err(e) if cli.serve_on_error => {
+                error!("Failed to load {:?}: {}", breach_path, e);
+                warn!("Serving the error until the file loads (--serve-on-error)");
+                let parsed = parser::ParsedContent {
+                    source_path: Some(breach_path.clone()),
+                    load_error: Some(e.to_string()),
+                    ..Default::default()
+                };
+                parser::prepare(parsed, &options, None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(cache_path) = &cache_path {
+            if let Err(e) = cache::store(cache_path, &prepared) {
+                warn!("Failed to write cache {:?}: {}", cache_path, e);
+            }
         }
+        (Some(breach_path), prepared)
     };
+    report::profile(&prepared.timings);
+    if prepared.parsed.load_error.is_none() {
+        info!("Breach content loaded successfully. Script present: {}", prepared.parsed.script.is_some());
+    }
+
+    if cli.show_config {
+        match (&breach_path, cli.remote_url()) {
+            (Some(path), _) => println!("Configuration for {}:", path.display()),
+            (None, Some(url)) => println!("Configuration for {}:", url),
+            (None, None) => println!("Configuration for <stdin>:"),
+        }
+        print!("{}", cli.describe_config(&prepared.parsed.config));
+        return Ok(());
+    }
+
+    if let Some(what) = cli.once {
+        if options.strict {
+            prepared.ensure_compiled()?;
+        }
+        output::print_once(&prepared, what)?;
+        return Ok(());
+    }
 
-    info!("Loading breach file: {:?}", breach_path);
-    let prepared = parser::load_prepared_from_file(&breach_path)?;
-    info!("Breach file loaded successfully. Script present: {}", prepared.parsed.script.is_some());
     let content = Arc::new(ArcSwap::from_pointee(prepared));
 
-    // Create broadcast channel for live reload notifications
-    let (reload_tx, _) = tokio::sync::broadcast::channel(100);
+    // In --no-serve mode, reload notifications drive output writes instead of
+    // browsers; subscribe before the watchers start so no change is missed
+    let emit_rx = cli.no_serve.then(|| reload_tx.subscribe());
 
     // Start file watcher with reload notifications
-    watch::watch_file(Arc::clone(&content), breach_path.clone(), reload_tx.clone());
+    let self_writes = watch::SelfWrites::default();
+    let write_format = cli.write_format();
+
+    // --emit writes the outputs next to serving them; the first write happens
+    // before the watchers start so creating the directory can't trigger a reload
+    if let Some(dir) = cli.emit.clone() {
+        if let Err(e) = output::write_emitted(&content.load(), &options, &dir, &write_format, &self_writes) {
+            error!("Failed to write outputs to {:?}: {}", dir, e);
+        }
+        let content = Arc::clone(&content);
+        let options = options.clone();
+        let self_writes = self_writes.clone();
+        on_every_reload(&reload_tx, move || {
+            if let Err(e) = output::write_emitted(&content.load(), &options, &dir, &write_format, &self_writes) {
+                error!("Failed to write outputs to {:?}: {}", dir, e);
+            }
+        });
+    }
+
+    // Keep the cache current so the next start can serve without compiling
+    if let Some(cache_path) = breach_path.as_deref().filter(|_| cli.use_cache()).map(cache::path_for) {
+        let content = Arc::clone(&content);
+        let self_writes = self_writes.clone();
+        let mut stored = content.load().content_key;
+        on_every_reload(&reload_tx, move || {
+            let prepared = content.load();
+            if prepared.content_key == stored {
+                return;
+            }
+            self_writes.record(&cache_path);
+            match cache::store(&cache_path, &prepared) {
+                Ok(()) => stored = prepared.content_key,
+                Err(e) => warn!("Failed to write cache {:?}: {}", cache_path, e),
+            }
+        });
+    }
+    let reload_stats = watch::ReloadStats::default();
+    reload_stats.check_ready(&content.load());
+    if let Some(path) = &breach_path {
+        watch::watch_file(
+            Arc::clone(&content),
+            path.clone(),
+            options.clone(),
+            cli.on_reload.clone(),
+            reload_tx.clone(),
+            self_writes.clone(),
+            reload_stats.clone(),
+        );
+    }
+    watch::watch_extra(cli.watch_extra.clone(), reload_tx.clone(), self_writes.clone());
+    let polling = cli.remote_url().zip(cli.poll_remote);
+    if cli.poll_remote.is_some() && polling.is_none() {
+        warn!("Ignoring --poll-remote, FILE is not a URL and is watched instead");
+    }
+    if let Some((url, secs)) = polling {
+        info!("Fetching {} again every {}s (--poll-remote)", url, secs);
+        watch::poll_remote(
+            Arc::clone(&content),
+            url.to_string(),
+            Duration::from_secs(secs),
+            options.clone(),
+            reload_tx.clone(),
+            reload_stats.clone(),
+        );
+    }
+
+    if let Some(mut emit_rx) = emit_rx {
+        if options.strict {
+            content.load().ensure_compiled()?;
+        }
+        output::emit(&content.load(), cli.out_dir.as_deref(), &write_format, &self_writes)?;
+        if breach_path.is_none() && polling.is_none() {
+            return Ok(());
+        }
+        info!("Watching for changes without serving. Press Ctrl+C to stop.");
+        loop {
+            match emit_rx.recv().await {
+                Ok(()) | Err(RecvError::Lagged(_)) => {
+                    // Keep the last good outputs on disk rather than writing broken ones
+                    if options.strict {
+                        if let Err(e) = content.load().ensure_compiled() {
+                            error!("Not writing outputs: {}", e);
+                            continue;
+                        }
+                    }
+                    if let Err(e) = output::emit(&content.load(), cli.out_dir.as_deref(), &write_format, &self_writes) {
+                        error!("Failed to write outputs: {}", e);
+                    }
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
 
     let state = server::AppState {
         content: Arc::clone(&content),
         reload_tx,
+        breach_path: breach_path.clone(),
+        options,
+        dev: cli.dev,
+        ws_clients: Arc::new(AtomicUsize::new(0)),
+        on_reload: cli.on_reload.clone(),
+        spa: cli.spa,
+        ignore_case: cli.ignore_case,
+        index_redirect: cli.index_redirect,
+        reload_stats,
+        activity: Arc::new(server::Activity::default()),
     };
 
-    info!(
-        "Server running on http://127.0.0.1:8080 serving {:?}",
-        breach_path
-    );
-    info!("Edit the .breach file while the server is running (live reload).");
+    let (addrs, listeners) = bind_port(&cli)?;
+
+    if addrs.iter().any(|addr| addr.ip().is_unspecified()) {
+        if let Some(ip) = lan_ip() {
+            info!("On your network: http://{}", SocketAddr::new(ip, addrs[0].port()));
+        }
+        if cli.auth.is_none() {
+            warn!("The server is reachable from other machines on the network and has no authentication (see --auth).");
+        }
+    }
+
+    match &breach_path {
+        Some(path) => {
+            info!("Server running on http://{} serving {:?}", addrs[0], path);
+            info!("Edit the .breach file while the server is running (live reload).");
+        }
+        None => match cli.remote_url() {
+            Some(url) => info!("Server running on http://{} serving {}", addrs[0], url),
+            None => info!("Server running on http://{} serving content from stdin", addrs[0]),
+        },
+    }
+
+    let response_headers = cli.response_headers();
+    let (auth, auth_skip_localhost) = (cli.auth.clone(), cli.auth_skip_localhost);
+    let idle_state = state.clone();
 
-    web::server(move || {
+    let mut http_server = web::server(move || {
+        let default_headers = response_headers
+            .iter()
+            .fold(web::middleware::DefaultHeaders::new(), |headers, (name, value)| {
+                headers.header(name.as_str(), value.as_str())
+            });
         web::App::new()
             .state(state.clone())
+            .wrap(default_headers)
+            .wrap(server::BasicAuth::new(auth.as_ref(), auth_skip_localhost))
+            .wrap(server::TrackActivity(Arc::clone(&state.activity)))
             .service(
                 web::resource("/")
                     .route(web::get().to(server::index))
@@ -85,13 +524,53 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 web::resource("/ws")
                     .route(web::get().to(server::ws_livereload))
             )
+            .service(
+                web::resource("/__breach")
+                    .route(web::get().to(server::dashboard))
+            )
+            .service(
+                web::resource("/__breach/")
+                    .route(web::get().to(server::dashboard))
+            )
+            .service(
+                web::resource("/__breach/files")
+                    .route(web::get().to(server::list_files))
+            )
+            .service(
+                web::resource("/__breach/health")
+                    .route(web::get().to(server::health))
+            )
+            .service(
+                web::resource("/__breach/ready")
+                    .route(web::get().to(server::ready))
+            )
+            .service(
+                web::resource("/__breach/live")
+                    .route(web::get().to(server::live))
+            )
+            .service(
+                web::resource("/__breach/reload")
+                    .route(web::post().to(server::trigger_reload))
+            )
             .default_service(
-                web::route().to(server::not_found)
+                web::route().to(server::fallback)
             )
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await?;
+    });
+    for listener in listeners {
+        http_server = http_server.listen(listener)?;
+    }
+    let workers = match cli.workers {
+        Some(n) => usize::from(n),
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    info!("Using {} HTTP worker(s)", workers);
+    let running = http_server.workers(workers).run();
+    if let Some(secs) = cli.timeout {
+        info!("Shutting down after {}s without requests or connected pages (--timeout)", secs);
+        ntex::rt::spawn(server::stop_when_idle(running.clone(), idle_state, Duration::from_secs(secs)));
+    }
+    running.await?;
+    report::print_profile();
 
     Ok(())
 }
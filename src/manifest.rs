@@ -0,0 +1,254 @@
+use crate::config;
+use crate::error::BreachError;
+use crate::parser;
+use std::path::{Path, PathBuf};
+
+/// File name of the manifest that assembles a virtual .breach file from separate
+/// files, one per section.
+pub const MANIFEST_NAME: &str = "breach.toml";
+
+/// Keys of the manifest that name section files, with the marker each file is
+/// placed under. Any other key is a setting, as in `¦config`.
+const SECTION_KEYS: &[&str] = &["html", "head", "css", "scss", "stylus", "js", "javascript", "ts", "typescript", "json", "data"];
+
+/// Returns true if `path` is a `breach.toml` manifest rather than a .breach file.
+pub fn is_manifest(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == MANIFEST_NAME)
+}
+
+/// Splits a manifest value into file paths: a single quoted string or a flat
+/// array of them (`["a.css", "b.css"]`).
+fn value_paths(value: &str) -> Vec<&str> {
+    match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        Some(list) => list
+            .split(',')
+            .map(|item| config::unquote(item.trim()))
+            .filter(|item| !item.is_empty())
+            .collect(),
+        None => vec![config::unquote(value)],
+    }
+}
+
+/// Returns `line` without its `# comment`, if it has one outside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// Net number of `[` opened and not closed in `value`, outside quotes.
+fn open_brackets(value: &str) -> i32 {
+    let mut quote = None;
+    let mut depth = 0;
+    for c in value.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+/// Splits a manifest into `key = value` entries with the line each starts on,
+/// comments removed and arrays spread over several lines joined. The manifest
+/// is read as a flat list of keys, not as full TOML: tables, inline tables and
+/// multi-line strings are refused instead of being misread.
+fn entries(manifest: &str) -> Result<Vec<(usize, String)>, (usize, String)> {
+    let mut entries: Vec<(usize, String)> = Vec::new();
+    let mut open: Option<(usize, String)> = None;
+    for (index, line) in manifest.lines().enumerate() {
+        let line_no = index + 1;
+        let trimmed = strip_comment(line).trim();
+        if let Some((start, mut entry)) = open.take() {
+            entry.push(' ');
+            entry.push_str(trimmed);
+            if open_brackets(&entry) > 0 {
+                open = Some((start, entry));
+            } else {
+                entries.push((start, entry));
+            }
+            continue;
+        }
+        if trimmed.is_empty() {
+            continue;
+        }
+        if trimmed.starts_with('[') {
+            let message = format!("tables like {} are not supported; list every file and setting as a top-level key", trimmed);
+            return Err((line_no, message));
+        }
+        let value = trimmed.split_once(['=', ':']).map_or("", |(_, value)| value.trim());
+        if value.starts_with("\"\"\"") || value.starts_with("'''") {
+            return Err((line_no, "multi-line strings are not supported".to_string()));
+        }
+        if value.starts_with('{') {
+            return Err((line_no, "inline tables are not supported".to_string()));
+        }
+        if open_brackets(value) > 0 {
+            open = Some((line_no, trimmed.to_string()));
+        } else {
+            entries.push((line_no, trimmed.to_string()));
+        }
+    }
+    match open {
+        Some((start, _)) => Err((start, "array is never closed with ']'".to_string())),
+        None => Ok(entries),
+    }
+}
+
+/// Builds .breach source from the manifest at `path`. Each section key names one
+/// or more files, relative to the manifest, whose contents are placed under that
+/// section's marker in the order the keys appear; the other keys become a
+/// `¦config` section. `¦include` lines in the files also search `include_dirs`.
+/// TOML syntax beyond top-level keys (tables, inline tables, multi-line strings)
+/// fails with [`BreachError::Manifest`].
+/// Every file read, manifest excluded, is appended to `dependencies` so it is
+/// watched like the manifest itself.
+pub fn assemble(
+    path: &Path,
+    max_size: u64,
+    include_dirs: &[PathBuf],
+    dependencies: &mut Vec<PathBuf>,
+) -> Result<String, BreachError> {
+    let bytes = parser::read_source_file(path, max_size)?;
+    let manifest = String::from_utf8_lossy(&bytes);
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+
+    let entries = entries(&manifest).map_err(|(line, message)| BreachError::Manifest {
+        path: path.to_path_buf(),
+        line,
+        message,
+    })?;
+    let mut source = String::new();
+    let mut settings = Vec::new();
+    for (_, entry) in &entries {
+        let trimmed = entry.as_str();
+        let key = trimmed.split(['=', ':']).next().unwrap_or_default().trim();
+        let Some(marker) = SECTION_KEYS.iter().find(|k| k.eq_ignore_ascii_case(key)) else {
+            settings.push(trimmed);
+            continue;
+        };
+        let value = trimmed[key.len()..].trim_start().trim_start_matches(['=', ':']).trim();
+        for file in value_paths(value) {
+            let file_path = base_dir.join(file);
+            let canonical = file_path
+                .canonicalize()
+                .map_err(|source| BreachError::ManifestFile { path: file_path.clone(), source })?;
+            let bytes = parser::read_source_file(&canonical, max_size).map_err(|e| match e {
+                BreachError::Io(source) => BreachError::ManifestFile { path: file_path.clone(), source },
+                other => other,
+            })?;
+            if !dependencies.contains(&canonical) {
+                dependencies.push(canonical.clone());
+            }
+            let contents = parser::decode_source(&bytes);
+            let contents = parser::expand_includes(
+                &contents,
+                Some(&canonical),
+                max_size,
+                include_dirs,
+                dependencies,
+            )?;
+            source.push_str(&format!("¦{}\n{}\n", marker, contents));
+        }
+    }
+    if !settings.is_empty() {
+        source.push_str("¦config\n");
+        for setting in settings {
+            source.push_str(setting);
+            source.push('\n');
+        }
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // A manifest with its files in a fresh directory, removed again when dropped
+    struct Project(PathBuf);
+
+    impl Project {
+        fn new(name: &str, manifest: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format!("breach-manifest-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(MANIFEST_NAME), manifest).unwrap();
+            for (file, contents) in files {
+                fs::write(dir.join(file), contents).unwrap();
+            }
+            Project(dir.canonicalize().unwrap())
+        }
+
+        fn assemble(&self) -> Result<(String, Vec<PathBuf>), BreachError> {
+            let mut dependencies = Vec::new();
+            let source = assemble(&self.0.join(MANIFEST_NAME), u64::MAX, &[], &mut dependencies)?;
+            Ok((source, dependencies))
+        }
+    }
+
+    impl Drop for Project {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const FILES: &[(&str, &str)] = &[("index.html", "<p>a</p>"), ("a.css", "p { top: 0 }"), ("b.css", "q { top: 1 }"), ("app.js", "let a;")];
+
+    #[test]
+    fn single_files_and_arrays_are_placed_in_key_order() {
+        let project = Project::new("forms", "js = \"app.js\"\nhtml = 'index.html'\ncss = [\"a.css\", \"b.css\"]\n", FILES);
+        let (source, _) = project.assemble().unwrap();
+        assert_eq!(source, "¦js\nlet a;\n¦html\n<p>a</p>\n¦css\np { top: 0 }\n¦css\nq { top: 1 }\n");
+    }
+
+    #[test]
+    fn comments_and_multi_line_arrays_are_read_as_toml() {
+        let manifest = "# site\nhtml = \"index.html\" # the page\ncss = [\n  \"a.css\", # first\n  \"b.css\",\n]\ntitle = \"A # B\" # shown in the tab\n";
+        let project = Project::new("toml", manifest, FILES);
+        let (source, _) = project.assemble().unwrap();
+        assert_eq!(source, "¦html\n<p>a</p>\n¦css\np { top: 0 }\n¦css\nq { top: 1 }\n¦config\ntitle = \"A # B\"\n");
+    }
+
+    #[test]
+    fn every_file_read_is_a_dependency() {
+        let files = [("index.html", "¦include part.html\n<p>a</p>"), ("part.html", "<nav></nav>"), ("a.css", "p { top: 0 }")];
+        let project = Project::new("dependencies", "html = \"index.html\"\ncss = [\"a.css\", \"a.css\"]\n", &files);
+        let (_, dependencies) = project.assemble().unwrap();
+        let expected: Vec<PathBuf> = ["index.html", "part.html", "a.css"].iter().map(|f| project.0.join(f)).collect();
+        assert_eq!(dependencies, expected);
+    }
+
+    #[test]
+    fn unsupported_toml_is_refused_with_its_line() {
+        for (manifest, line) in [
+            ("html = \"index.html\"\n[files]\ncss = \"a.css\"\n", 2),
+            ("css = [\"a.css\",\n\"b.css\"\n", 1),
+            ("html = \"index.html\"\ntitle = \"\"\"long\"\"\"\n", 2),
+            ("style = { css = \"a.css\" }\n", 1),
+        ] {
+            let project = Project::new("refused", manifest, FILES);
+            match project.assemble() {
+                Err(BreachError::Manifest { line: found, .. }) => assert_eq!(found, line, "{}", manifest),
+                other => panic!("{:?} for {}", other.map(|(source, _)| source), manifest),
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+/// Minifies compiled CSS by running it through grass with compressed output.
+/// CSS grass can't read (e.g. raw fallback source) is returned unchanged.
+pub fn css(css: &str) -> String {
+    let options = grass::Options::default().style(grass::OutputStyle::Compressed);
+    let compile = || grass::from_string(css.to_string(), &options);
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(compile)) {
+        Ok(Ok(minified)) => minified,
+        Ok(Err(e)) => {
+            tracing::debug!("Not minifying CSS grass can't parse: {}", e);
+            css.to_string()
+        }
+        Err(_) => {
+            tracing::debug!("Not minifying CSS, grass panicked on it");
+            css.to_string()
+        }
+    }
+}
+
+/// Returns whether a `/` after `prev` (the last significant character),
+/// `before` (the one before it) and `word` (the identifier or keyword ending at
+/// `prev`, if any) starts a regular expression rather than a division, or `None`
+/// when that can't be told without parsing: after `)` (`if (ok) /re/` vs
+/// `(a) / b`), `}` (a block vs an object) and `++`/`--` (prefix vs postfix).
+fn starts_regex(prev: Option<char>, before: Option<char>, word: &str) -> Option<bool> {
+    match prev {
+        None => Some(true),
+        Some(c) if c.is_alphanumeric() || matches!(c, '_' | '$') => Some(matches!(
+            word,
+            "return" | "typeof" | "instanceof" | "in" | "of" | "new" | "delete" | "void" | "throw" | "case"
+                | "do" | "else" | "yield" | "await"
+        )),
+        Some(')' | '}') => None,
+        Some(c @ ('+' | '-')) if before == Some(c) => None,
+        Some(c) => Some(!matches!(c, ']' | '"' | '\'' | '`')),
+    }
+}
+
+/// Minifies JavaScript conservatively: comments, indentation, blank lines and
+/// runs of spaces go, while strings, template literals and regular expressions
+/// are copied verbatim. Line breaks are kept so automatic semicolon insertion
+/// reads the code the same way. Source with a `/` that may start either a
+/// regular expression or a division is returned unchanged, since guessing wrong
+/// would read the rest of the line as something else.
+pub fn js(source: &str) -> String {
+    match try_js(source) {
+        Some(minified) => minified,
+        None => {
+            tracing::debug!("Not minifying JavaScript with an ambiguous '/'");
+            source.to_string()
+        }
+    }
+}
+
+fn try_js(source: &str) -> Option<String> {
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let (mut prev, mut before): (Option<char>, Option<char>) = (None, None);
+    let mut word = String::new();
+    let mut pending_space = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                let mut multiline = false;
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    multiline |= c == '\n';
+                    last = c;
+                }
+                // A comment spanning lines still ends the line for semicolon insertion
+                if multiline && !out.is_empty() && !out.ends_with('\n') {
+                    out.push('\n');
+                    pending_space = false;
+                } else {
+                    pending_space = true;
+                }
+            }
+            '\n' => {
+                pending_space = false;
+                if !out.is_empty() && !out.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+            c if c.is_whitespace() => pending_space = true,
+            c => {
+                if pending_space && !out.is_empty() && !out.ends_with('\n') {
+                    out.push(' ');
+                }
+                pending_space = false;
+                out.push(c);
+                match c {
+                    '"' | '\'' | '`' => copy_literal(&mut chars, &mut out, c),
+                    '/' if starts_regex(prev, before, &word)? => copy_regex(&mut chars, &mut out),
+                    _ => {}
+                }
+                if c.is_alphanumeric() || matches!(c, '_' | '$') {
+                    word.push(c);
+                } else {
+                    word.clear();
+                }
+                before = prev;
+                prev = Some(c);
+            }
+        }
+    }
+    out.truncate(out.trim_end().len());
+    Some(out)
+}
+
+/// Copies a string or template literal opened by `quote` up to and including its
+/// closing quote.
+fn copy_literal(chars: &mut std::iter::Peekable<std::str::Chars>, out: &mut String, quote: char) {
+    while let Some(c) = chars.next() {
+        out.push(c);
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                out.push(escaped);
+            }
+        } else if c == quote {
+            return;
+        }
+    }
+}
+
+/// Copies a regular expression literal after its opening `/`, up to and
+/// including its closing `/`. A `/` inside a character class doesn't end it.
+fn copy_regex(chars: &mut std::iter::Peekable<std::str::Chars>, out: &mut String) {
+    let mut in_class = false;
+    while let Some(c) = chars.next() {
+        out.push(c);
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    out.push(escaped);
+                }
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => return,
+            '\n' => return,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_and_indentation_go() {
+        let source = "// header\nfunction f(a) {\n    /* note */ return a; // done\n\n}\n";
+        assert_eq!(js(source), "function f(a) {\nreturn a;\n}");
+    }
+
+    #[test]
+    fn a_comment_spanning_lines_still_ends_the_line() {
+        assert_eq!(js("return /*\n*/ a"), "return\na");
+    }
+
+    #[test]
+    fn strings_and_template_literals_are_copied_verbatim() {
+        assert_eq!(js("let u = 'http://a  b'; // c"), "let u = 'http://a  b';");
+        assert_eq!(js("let q = \"a \\\" //  b\";"), "let q = \"a \\\" //  b\";");
+        let template = "let t = `\n    line // kept\n    /* kept */\n`;";
+        assert_eq!(js(template), template);
+    }
+
+    #[test]
+    fn regex_literals_are_copied_verbatim() {
+        assert_eq!(js("let re = /[//]  x/g; // c"), "let re = /[//]  x/g;");
+        assert_eq!(js("return /a\\/ b/.test(s)"), "return /a\\/ b/.test(s)");
+        assert_eq!(js("let half = a  /  2; // c"), "let half = a / 2;");
+    }
+
+    #[test]
+    fn an_ambiguous_slash_leaves_the_source_alone() {
+        let source = "if (ok) /[//]/.test(s) && run()\nnext()";
+        assert_eq!(js(source), source);
+        for source in ["let x = (a + b) / 2 // c", "let y = {} / 2", "i++ / 2"] {
+            assert_eq!(js(source), source);
+        }
+    }
+
+    #[test]
+    fn css_grass_cannot_read_is_left_alone() {
+        assert_eq!(css("a {  color: red;  }"), "a{color:red}");
+        assert_eq!(css("a { color: "), "a { color: ");
+    }
+}
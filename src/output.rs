@@ -0,0 +1,484 @@
+use crate::parser::{self, PrepareOptions, PreparedContent};
+use crate::watch::SelfWrites;
+use std::{
+    fmt,
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+use tracing::{info, warn};
+
+/// What `--once` prints.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnceOutput {
+    /// The final HTML, with links and title injected.
+    #[default]
+    Html,
+    /// The compiled stylesheet.
+    Css,
+    /// The processed script.
+    Js,
+    /// Every output, each under a `==> name <==` header.
+    All,
+}
+
+impl std::str::FromStr for OnceOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "html" => Ok(OnceOutput::Html),
+            "css" => Ok(OnceOutput::Css),
+            "js" => Ok(OnceOutput::Js),
+            "all" => Ok(OnceOutput::All),
+            other => Err(format!("unknown output '{}' (expected 'html', 'css', 'js' or 'all')", other)),
+        }
+    }
+}
+
+impl fmt::Display for OnceOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OnceOutput::Html => "html",
+            OnceOutput::Css => "css",
+            OnceOutput::Js => "js",
+            OnceOutput::All => "all",
+        })
+    }
+}
+
+/// How output files are formatted when written to disk, so committed build
+/// output matches what downstream tools expect.
+#[derive(Clone, Copy, Debug)]
+pub struct WriteFormat {
+    /// End every file with exactly one newline; otherwise write it as compiled.
+    pub final_newline: bool,
+    /// Keep `sourceMappingURL` comments in the CSS and JS.
+    pub source_map_comments: bool,
+    /// Remove the indentation shared by every line of a file.
+    pub dedent: bool,
+}
+
+impl Default for WriteFormat {
+    fn default() -> Self {
+        Self {
+            final_newline: true,
+            source_map_comments: true,
+            dedent: false,
+        }
+    }
+}
+
+impl WriteFormat {
+    /// Applies the format to the body of the output file `name`. Lines keep
+    /// their own endings, so CRLF output stays CRLF.
+    fn apply(&self, name: &str, body: &[u8]) -> Vec<u8> {
+        let Ok(text) = std::str::from_utf8(body) else {
+            return body.to_vec();
+        };
+        let mut lines: Vec<(&str, &str)> = text.split_inclusive('\n').map(split_line_ending).collect();
+        if !self.source_map_comments && !name.ends_with(".html") {
+            lines.retain(|(line, _)| !is_source_map_comment(line));
+        }
+        let indent = if self.dedent {
+            lines
+                .iter()
+                .filter(|(line, _)| !line.trim().is_empty())
+                .map(|(line, _)| line.len() - line.trim_start().len())
+                .min()
+                .unwrap_or(0)
+        } else {
+            0
+        };
+        let mut out: String = lines
+            .iter()
+            .flat_map(|(line, ending)| [line.get(indent..).unwrap_or_else(|| line.trim_start()), ending])
+            .collect();
+        if self.final_newline {
+            out.truncate(out.trim_end().len());
+            out.push_str(if text.contains("\r\n") { "\r\n" } else { "\n" });
+        } else if !text.ends_with('\n') {
+            // A removed last line mustn't leave the ending of the one before it
+            out.truncate(out.trim_end_matches(['\r', '\n']).len());
+        }
+        out.into_bytes()
+    }
+}
+
+/// Splits a line from `split_inclusive('\n')` into its text and its ending
+/// (`\r\n`, `\n`, or nothing for a last line without one).
+fn split_line_ending(line: &str) -> (&str, &str) {
+    let text = line.strip_suffix('\n').map_or(line, |l| l.strip_suffix('\r').unwrap_or(l));
+    line.split_at(text.len())
+}
+
+/// Returns true for a `//# sourceMappingURL=` or `/*# sourceMappingURL= */` line.
+fn is_source_map_comment(line: &str) -> bool {
+    let line = line.trim();
+    ["//# sourceMappingURL=", "//@ sourceMappingURL=", "/*# sourceMappingURL="]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+}
+
+/// Returns the file name and body of each present output section; a styling
+/// variant's page is written as `<name>.html`. Assets are named by their
+/// content hash when the HTML links them that way.
+fn output_files(prepared: &PreparedContent) -> Vec<(String, &[u8])> {
+    let bodies = &prepared.bodies;
+    let name = |stem: &str, ext: &str, body: &[u8]| {
+        if bodies.hashed_names {
+            parser::hashed_name(stem, ext, parser::body_version(body))
+        } else {
+            format!("{}.{}", stem, ext)
+        }
+    };
+    let mut files = Vec::new();
+    if let Some(html) = &bodies.html {
+        files.push(("index.html".to_string(), &html[..]));
+    }
+    if let Some(css) = &bodies.styling {
+        files.push((name("style", "css", css), &css[..]));
+    }
+    for media in &bodies.media {
+        files.push((name(&format!("style.{}", media.slug), "css", &media.body), &media.body[..]));
+    }
+    if let Some(js) = &bodies.script {
+        files.push((name("script", "js", js), &js[..]));
+    }
+    for variant in &bodies.variants {
+        if let Some(html) = &variant.html {
+            files.push((format!("{}.html", variant.name), &html[..]));
+        }
+        if let Some(css) = &variant.styling {
+            files.push((name(&format!("style@{}", variant.name), "css", css), &css[..]));
+        }
+    }
+    files
+}
+
+/// Returns the local path a `src`, `href` or `url()` reference points to,
+/// relative to the page, or `None` for URLs with a scheme (`https:`, `data:`),
+/// protocol-relative URLs and fragments. Queries and fragments are dropped.
+fn local_reference(reference: &str) -> Option<&str> {
+    let reference = reference.trim();
+    let path = reference.split(['?', '#']).next().unwrap_or_default();
+    let has_scheme = path.find(':').is_some_and(|colon| !path[..colon].contains('/'));
+    if has_scheme || path.starts_with("//") || path.starts_with("/__breach/") {
+        return None;
+    }
+    let path = path.trim_start_matches("./").trim_start_matches('/');
+    (!path.is_empty()).then_some(path)
+}
+
+/// Values of the `src` and `href` attributes in `html`, quoted or not. The
+/// name must follow whitespace, so `data-src` and `srcset` aren't read.
+fn attribute_references(html: &str) -> Vec<&str> {
+    let lower = html.to_ascii_lowercase();
+    let mut references = Vec::new();
+    for attribute in ["src", "href"] {
+        for (pos, _) in lower.match_indices(attribute) {
+            if !lower[..pos].ends_with(|c: char| c.is_ascii_whitespace()) {
+                continue;
+            }
+            let Some(value) = html[pos + attribute.len()..].trim_start().strip_prefix('=') else {
+                continue;
+            };
+            let value = value.trim_start();
+            let value = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => value[1..].split(quote).next(),
+                _ => value.split(|c: char| c.is_whitespace() || c == '>').next(),
+            };
+            references.extend(value);
+        }
+    }
+    references
+}
+
+/// Targets of the `url()` references in `css`, without their quotes.
+fn css_references(css: &str) -> Vec<&str> {
+    css.match_indices("url(")
+        .filter_map(|(pos, _)| {
+            let value = &css[pos + "url(".len()..];
+            let value = &value[..value.find(')')?];
+            Some(value.trim().trim_matches(['"', '\'']))
+        })
+        .collect()
+}
+
+/// Local files the served content links to: `src` and `href` attributes in the
+/// HTML and `url()` references in the stylesheets, relative to the source file.
+fn referenced_assets(prepared: &PreparedContent) -> Vec<&str> {
+    let bodies = &prepared.bodies;
+    let mut references = Vec::new();
+    let pages = bodies.html.iter().chain(bodies.variants.iter().filter_map(|v| v.html.as_ref()));
+    for html in pages.filter_map(|html| std::str::from_utf8(html).ok()) {
+        references.extend(attribute_references(html));
+    }
+    let stylesheets = bodies
+        .styling
+        .iter()
+        .chain(bodies.media.iter().map(|m| &m.body))
+        .chain(bodies.variants.iter().filter_map(|v| v.styling.as_ref()));
+    for css in stylesheets.filter_map(|css| std::str::from_utf8(css).ok()) {
+        references.extend(css_references(css));
+    }
+    references.into_iter().filter_map(local_reference).collect()
+}
+
+/// Copies the local files the page references, and those matching the `assets`
+/// config globs, from next to the source file into `dir`, keeping their
+/// relative paths so the written site works on its own. Referenced files that
+/// don't exist are reported and skipped; files already copied and unchanged
+/// since are left alone. Returns the copied paths.
+fn copy_assets(prepared: &PreparedContent, dir: &Path, self_writes: &SelfWrites) -> io::Result<Vec<PathBuf>> {
+    let root = prepared
+        .parsed
+        .source_path
+        .as_deref()
+        .and_then(Path::parent)
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let outputs: Vec<String> = output_files(prepared).into_iter().map(|(name, _)| name).collect();
+
+    let mut assets: Vec<PathBuf> = Vec::new();
+    for reference in referenced_assets(prepared) {
+        if outputs.iter().any(|name| name == reference) {
+            continue;
+        }
+        if root.join(reference).is_file() {
+            assets.push(PathBuf::from(reference));
+        } else {
+            warn!("The page references {}, which doesn't exist in {:?}; not copying it", reference, root);
+        }
+    }
+    for pattern in &prepared.parsed.config.assets {
+        let matches = match glob::glob(&root.join(pattern).to_string_lossy()) {
+            Ok(paths) => paths.flatten().filter(|path| path.is_file()).collect::<Vec<_>>(),
+            Err(e) => {
+                warn!("Invalid 'assets' pattern '{}': {}", pattern, e);
+                continue;
+            }
+        };
+        if matches.is_empty() {
+            warn!("The 'assets' pattern '{}' matches no file in {:?}", pattern, root);
+        }
+        assets.extend(matches.iter().filter_map(|path| path.strip_prefix(root).ok()).map(Path::to_path_buf));
+    }
+    assets.sort();
+    assets.dedup();
+
+    let mut copied = Vec::new();
+    for asset in assets {
+        // Only paths below the source directory are copied, so `../` can't write outside `dir`
+        if !asset.components().all(|c| matches!(c, Component::Normal(_))) {
+            warn!("Not copying {:?}, which is outside {:?}", asset, root);
+            continue;
+        }
+        let (source, target) = (root.join(&asset), dir.join(&asset));
+        if is_up_to_date(&source, &target) {
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self_writes.record(&target);
+        fs::copy(&source, &target)?;
+        copied.push(target);
+    }
+    Ok(copied)
+}
+
+/// Whether `target` is a copy of `source` made since it last changed.
+fn is_up_to_date(source: &Path, target: &Path) -> bool {
+    let (Ok(source), Ok(target)) = (fs::metadata(source), fs::metadata(target)) else {
+        return false;
+    };
+    let modified = |meta: &fs::Metadata| meta.modified().ok();
+    source.len() == target.len() && modified(&target).is_some_and(|t| modified(&source).is_some_and(|s| t >= s))
+}
+
+/// Writes the compiled HTML, CSS and JS into `dir` as `index.html`, `style.css`
+/// and `script.js` (or their hashed names), formatted per `format`, creating the
+/// directory if needed, and copies the local assets the page uses next to them.
+/// The paths are recorded in `self_writes` before writing so watchers ignore
+/// them. Returns the written paths.
+pub fn write_outputs(
+    prepared: &PreparedContent,
+    dir: &Path,
+    format: &WriteFormat,
+    self_writes: &SelfWrites,
+) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    for (name, body) in output_files(prepared) {
+        let path = dir.join(&name);
+        self_writes.record(&path);
+        fs::write(&path, format.apply(&name, body))?;
+        written.push(path);
+    }
+    written.extend(copy_assets(prepared, dir, self_writes)?);
+    Ok(written)
+}
+
+/// Prints the compiled outputs to stdout, each preceded by a `==> name <==` header.
+pub fn print_outputs(prepared: &PreparedContent) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (name, body) in output_files(prepared) {
+        writeln!(out, "==> {} <==", name)?;
+        out.write_all(body)?;
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+/// Prints the output selected by `--once` to stdout. A single section is printed
+/// as-is, so it can be piped into other tools; a missing section prints nothing.
+pub fn print_once(prepared: &PreparedContent, what: OnceOutput) -> io::Result<()> {
+    let body = match what {
+        OnceOutput::All => return print_outputs(prepared),
+        OnceOutput::Html => prepared.bodies.html.as_deref(),
+        OnceOutput::Css => prepared.bodies.styling.as_deref(),
+        OnceOutput::Js => prepared.bodies.script.as_deref(),
+    };
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if let Some(body) = body {
+        out.write_all(body)?;
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+/// Writes the outputs of the served content `served` into `dir` for `--emit`.
+/// The content is prepared again without the livereload client; the compiled
+/// styling is reused from `served`, so this only redoes the injection.
+pub fn write_emitted(
+    served: &PreparedContent,
+    options: &PrepareOptions,
+    dir: &Path,
+    format: &WriteFormat,
+    self_writes: &SelfWrites,
+) -> io::Result<Vec<PathBuf>> {
+    let options = PrepareOptions { livereload: false, ..options.clone() };
+    let emitted = parser::prepare(served.parsed.clone(), &options, Some(served));
+    write_outputs(&emitted, dir, format, self_writes)
+}
+
+/// Emits the outputs to `out_dir` when given, otherwise to stdout.
+pub fn emit(
+    prepared: &PreparedContent,
+    out_dir: Option<&Path>,
+    format: &WriteFormat,
+    self_writes: &SelfWrites,
+) -> io::Result<()> {
+    match out_dir {
+        Some(dir) => {
+            for path in write_outputs(prepared, dir, format, self_writes)? {
+                info!("Wrote {:?}", path);
+            }
+            Ok(())
+        }
+        None => print_outputs(prepared),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(format: WriteFormat, name: &str, body: &str) -> String {
+        String::from_utf8(format.apply(name, body.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn files_end_with_exactly_one_newline_by_default() {
+        let format = WriteFormat::default();
+        assert_eq!(apply(format, "style.css", "p{}"), "p{}\n");
+        assert_eq!(apply(format, "style.css", "p{}\n\n  \n"), "p{}\n");
+        let as_compiled = WriteFormat { final_newline: false, ..format };
+        assert_eq!(apply(as_compiled, "style.css", "p{}"), "p{}");
+        assert_eq!(apply(as_compiled, "style.css", "p{}\n\n"), "p{}\n\n");
+    }
+
+    #[test]
+    fn line_endings_are_kept() {
+        let format = WriteFormat::default();
+        assert_eq!(apply(format, "index.html", "<p>\r\n  a\r\n</p>\r\n\r\n"), "<p>\r\n  a\r\n</p>\r\n");
+        assert_eq!(apply(format, "script.js", "a\r\nb\nc"), "a\r\nb\nc\r\n");
+        assert_eq!(apply(WriteFormat { final_newline: false, ..format }, "script.js", "a\r\nb"), "a\r\nb");
+    }
+
+    #[test]
+    fn dedent_removes_only_the_shared_indentation() {
+        let format = WriteFormat { dedent: true, ..Default::default() };
+        assert_eq!(apply(format, "index.html", "    <ul>\n\n      <li>a</li>\n    </ul>\n"), "<ul>\n\n  <li>a</li>\n</ul>\n");
+        assert_eq!(apply(WriteFormat::default(), "index.html", "  <p>a</p>\n"), "  <p>a</p>\n");
+    }
+
+    #[test]
+    fn source_map_comments_are_dropped_from_css_and_js_only() {
+        let format = WriteFormat { source_map_comments: false, final_newline: false, ..Default::default() };
+        assert_eq!(apply(format, "style.css", "p{}\n/*# sourceMappingURL=style.css.map */"), "p{}");
+        assert_eq!(apply(format, "script.js", "a;\n//# sourceMappingURL=script.js.map\n"), "a;\n");
+        let html = "<p>a</p>\n//# sourceMappingURL=x\n";
+        assert_eq!(apply(format, "index.html", html), html);
+        assert_eq!(apply(WriteFormat::default(), "script.js", "a;\n//# sourceMappingURL=x"), "a;\n//# sourceMappingURL=x\n");
+    }
+
+    #[test]
+    fn binary_bodies_are_written_as_they_are() {
+        let body = [0xff, 0xfe, b'\n', b' '];
+        assert_eq!(WriteFormat::default().apply("favicon.ico", &body), body);
+    }
+
+    #[test]
+    fn only_local_paths_are_references() {
+        for url in ["https://cdn.test/a.png", "data:image/png;base64,AA", "mailto:a@b.test", "//cdn.test/a.png"] {
+            assert_eq!(local_reference(url), None, "{}", url);
+        }
+        for url in ["/__breach/livereload.js", "#top", "", "?v=1"] {
+            assert_eq!(local_reference(url), None, "{}", url);
+        }
+        assert_eq!(local_reference(" img/a.png?v=2#x "), Some("img/a.png"));
+        assert_eq!(local_reference("./img/a.png"), Some("img/a.png"));
+        assert_eq!(local_reference("/img/a:b.png"), Some("img/a:b.png"));
+    }
+
+    #[test]
+    fn quoted_and_unquoted_attributes_are_found() {
+        let html = "<img src=\"a.png\"><a href='b.html'>b</a><img\n  src=c.png>\n<link\thref = d.css>";
+        assert_eq!(attribute_references(html), ["a.png", "c.png", "b.html", "d.css"]);
+        let html = "<img data-src=\"x.png\" srcset=\"y.png 2x\"><p>src=z.png</p><img SRC=e.png>";
+        assert_eq!(attribute_references(html), ["e.png"]);
+    }
+
+    #[test]
+    fn quoted_and_unquoted_css_urls_are_found() {
+        let css = "a{background:url(a.png)}b{background:url( \"b.png\" )}c{src:url('c.woff')}";
+        assert_eq!(css_references(css), ["a.png", "b.png", "c.woff"]);
+    }
+
+    #[test]
+    fn assets_outside_the_source_directory_are_not_copied() {
+        let root = std::env::temp_dir().join(format!("breach-output-assets-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (site, out) = (root.join("site"), root.join("build/out"));
+        fs::create_dir_all(site.join("img")).unwrap();
+        fs::write(site.join("img/a.png"), "png").unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+
+        let source = "¦html\n<img src=\"img/a.png\"><img src=../secret.txt><img src=https://cdn.test/b.png>\n";
+        let mut parsed = parser::parse_breach_content(source);
+        parsed.source_path = Some(site.join("index.breach"));
+        let options = PrepareOptions { livereload: false, ..Default::default() };
+        let prepared = parser::prepare(parsed, &options, None);
+
+        let copied = copy_assets(&prepared, &out, &SelfWrites::default()).unwrap();
+        assert_eq!(copied, [out.join("img/a.png")]);
+        assert_eq!(fs::read_to_string(out.join("img/a.png")).unwrap(), "png");
+        assert!(!root.join("build/secret.txt").exists());
+        assert!(copy_assets(&prepared, &out, &SelfWrites::default()).unwrap().is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,6 +1,23 @@
+use crate::config::{self, BreachConfig, ScriptType};
+use crate::error::{BreachError, ScssError, StylusError};
+use crate::manifest;
+use crate::minify;
+use crate::scope;
+use encoding_rs::Encoding;
 use fxhash::FxHasher64;
+use ntex::util::Bytes;
+use std::borrow::Cow;
+use std::ops::Range;
 use std::hash::Hasher;
-use std::{error::Error, fs, path::PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 /// Represents the parsed content sections from a .breach file.
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
@@ -11,6 +28,59 @@ pub struct ParsedContent {
     pub styling: Option<String>,
     /// The script section content (e.g., JavaScript, TypeScript, CoffeeScript), if present.
     pub script: Option<String>,
+    /// Content of the `¦head` section, injected verbatim into the page's `<head>`.
+    pub head: Option<String>,
+    /// JSON from the `¦json` (or `¦data`) section, filling `{{ path }}`
+    /// placeholders in the markup.
+    pub data: Option<String>,
+    /// Settings from the `¦config` section.
+    pub config: BreachConfig,
+    /// The file the content was loaded from, named in compile errors; `None` for stdin.
+    pub source_path: Option<PathBuf>,
+    /// Files spliced in through `¦include`, which are watched alongside the source.
+    pub dependencies: Vec<PathBuf>,
+    /// Styling blocks with a `media="..."` directive, each served as its own stylesheet.
+    pub media_styling: Vec<MediaStyling>,
+    /// Styling of the named variants (`¦css@print`), each served as its own page.
+    pub variants: Vec<StylingVariant>,
+    /// The `favicon.ico` next to the source file, if there is one.
+    pub favicon: Option<Bytes>,
+    /// Why the source could not be reloaded (e.g. a broken manifest) while these
+    /// older sections are served instead; shown in the error overlay.
+    pub load_error: Option<String>,
+}
+
+/// Styling for one media query, served at `/style.<slug>.css` and linked with a
+/// `media` attribute instead of being merged into `/style.css`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MediaStyling {
+    /// The media query, e.g. `print` or `(max-width: 600px)`.
+    pub media: String,
+    /// URL-safe name derived from the media query.
+    pub slug: String,
+    /// The styling content, in the same marker format as [`ParsedContent::styling`].
+    pub styling: String,
+}
+
+/// Styling for a named variant (`¦css@print`). The variant's page is the same
+/// markup with this styling linked after the shared stylesheets, so its rules
+/// win over them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StylingVariant {
+    /// The name after the `@`, lowercased.
+    pub name: String,
+    /// Where the page is served: `/<name>`, unless a marker gives `route=`.
+    pub route: String,
+    /// The styling content, in the same marker format as [`ParsedContent::styling`].
+    pub styling: String,
+}
+
+/// Byte sizes of each section as written in the source, before compilation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SectionSizes {
+    pub markup: usize,
+    pub styling: usize,
+    pub script: usize,
 }
 
 /// Represents the prepared content ready for serving, with injected links and fingerprint.
@@ -18,18 +88,412 @@ pub struct ParsedContent {
 pub struct PreparedContent {
     /// The original parsed content sections.
     pub parsed: ParsedContent,
-    /// HTML content with CSS and JS links injected, prepared once for efficiency.
-    pub html_injected: Option<String>,
     /// A hash-based fingerprint of the content for cache busting and change detection.
     pub fingerprint: u64,
+    /// Compile errors encountered while preparing, shown in the browser error overlay.
+    pub errors: Vec<String>,
+    /// The styling compile errors among `errors`. Only these are reused along
+    /// with the compiled CSS when the styling is unchanged; load and `¦json`
+    /// errors are found again on every preparation.
+    pub styling_errors: Vec<String>,
+    /// Section sizes before compilation, for diagnostics.
+    pub source_sizes: SectionSizes,
+    /// When this content was prepared.
+    pub prepared_at: SystemTime,
+    /// How long each phase of `prepare()` took.
+    pub timings: PrepareTimings,
+    /// Fingerprints of the individual served sections, to tell which ones changed.
+    pub section_fingerprints: SectionFingerprints,
+    /// The compiled outputs, as served.
+    pub bodies: ServedBodies,
+    /// Hash of the styling sources the bodies were compiled from, so the next
+    /// `prepare()` can skip compiling unchanged styling. `None` when nothing was
+    /// prepared yet.
+    pub styling_source_hash: Option<u64>,
+    /// Hash of everything the outputs were prepared from (see [`content_key`]),
+    /// so preparing the same content again reuses them whole. `None` when
+    /// nothing was prepared yet.
+    pub content_key: Option<u64>,
+    /// Minified stylesheet and script, computed on first use.
+    pub minified: MinifiedBodies,
+}
+
+/// Minified variants of the served stylesheet and script, for `?min=1` requests
+/// under `--dev`. Each is computed on the first request and kept with the
+/// content it was minified from.
+#[derive(Clone, Debug, Default)]
+pub struct MinifiedBodies {
+    styling: LazyBody,
+    script: LazyBody,
+}
+
+/// A response body derived from the prepared content on first use, then shared.
+/// The work runs once: requests arriving while it runs (e.g. every open tab
+/// right after a reload) wait for that result instead of starting their own.
+#[derive(Clone, Debug, Default)]
+pub struct LazyBody(OnceLock<Option<Bytes>>);
+
+impl LazyBody {
+    /// Returns the body, running `compute` if no request has yet.
+    pub fn get_or_compute(&self, what: &str, compute: impl FnOnce() -> Option<Bytes>) -> Option<Bytes> {
+        self.0
+            .get_or_init(|| {
+                let started = Instant::now();
+                let body = compute();
+                tracing::debug!("Computed {} in {:?}", what, started.elapsed());
+                body
+            })
+            .clone()
+    }
+}
+
+/// The served outputs as response bodies. They are built once per preparation
+/// and are the only copy of the output, so requests share the buffers instead
+/// of copying them.
+#[derive(Clone, Debug, Default)]
+pub struct ServedBodies {
+    /// The HTML with stylesheet and script links, title and livereload injected.
+    pub html: Option<Bytes>,
+    /// The compiled `/style.css`.
+    pub styling: Option<Bytes>,
+    /// The processed `/script.js`.
+    pub script: Option<Bytes>,
+    /// The compiled media stylesheets.
+    pub media: Vec<ServedMedia>,
+    /// The pages and stylesheets of the styling variants.
+    pub variants: Vec<ServedVariant>,
+    /// The `/favicon.ico` read from next to the source.
+    pub favicon: Option<Bytes>,
+    /// Whether the HTML links the stylesheets and script by content-hashed file
+    /// names (`style.<hash>.css`) instead of `?v=` URLs.
+    pub hashed_names: bool,
+}
+
+/// A compiled media stylesheet, served at `/style.<slug>.css`.
+#[derive(Clone, Debug)]
+pub struct ServedMedia {
+    /// The media query the stylesheet is linked with.
+    pub media: String,
+    /// URL-safe name derived from the media query.
+    pub slug: String,
+    pub body: Bytes,
+}
+
+/// A styling variant as served: its page at `route` and its compiled
+/// stylesheet at `/style@<name>.css`.
+#[derive(Clone, Debug)]
+pub struct ServedVariant {
+    pub name: String,
+    pub route: String,
+    /// The page, linking the variant stylesheet; `None` without markup.
+    pub html: Option<Bytes>,
+    pub styling: Option<Bytes>,
+}
+
+/// Per-section fingerprints of the served content: the filled markup with its
+/// `¦head`, and the compiled styling and script. A missing section hashes like an
+/// empty one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SectionFingerprints {
+    pub markup: u64,
+    pub styling: u64,
+    pub script: u64,
+}
+
+/// Hashes everything the compiled styling depends on: the main and media styling
+/// sources, whether comments are stripped, whether preprocessing is skipped and the
+/// files in the include directories SCSS may import.
+fn styling_source_hash(parsed: &ParsedContent, strip_comments: bool, options: &PrepareOptions) -> u64 {
+    let include_dirs = &options.include_dirs;
+    let mut hasher = FxHasher64::default();
+    for dir in include_dirs {
+        hash_dir_stamp(&mut hasher, dir);
+    }
+    hasher.write(parsed.styling.as_deref().unwrap_or_default().as_bytes());
+    for m in &parsed.media_styling {
+        hasher.write(m.media.as_bytes());
+        hasher.write(m.slug.as_bytes());
+        hasher.write(m.styling.as_bytes());
+    }
+    for v in &parsed.variants {
+        hasher.write(v.name.as_bytes());
+        hasher.write(v.styling.as_bytes());
+    }
+    hasher.write_u8(strip_comments as u8);
+    hasher.write_u8(options.raw_styling as u8);
+    hasher.finish()
+}
+
+/// Hashes everything `prepare()` output depends on: every parsed section and
+/// its config, the favicon, the styling inputs (`styling_hash`, see
+/// [`styling_source_hash`]) and the options. Content with the same key prepares
+/// to the same outputs.
+fn content_key(parsed: &ParsedContent, styling_hash: u64, options: &PrepareOptions) -> u64 {
+    let mut hasher = FxHasher64::default();
+    let mut write = |bytes: Option<&[u8]>| {
+        hasher.write_u8(bytes.is_some() as u8);
+        hasher.write_usize(bytes.map_or(0, <[u8]>::len));
+        hasher.write(bytes.unwrap_or_default());
+    };
+    for section in [&parsed.markup, &parsed.script, &parsed.head, &parsed.data, &parsed.load_error] {
+        write(section.as_deref().map(str::as_bytes));
+    }
+    write(parsed.favicon.as_deref());
+    write(parsed.source_path.as_deref().map(|p| p.as_os_str().as_encoded_bytes()));
+    for variant in &parsed.variants {
+        write(Some(variant.route.as_bytes()));
+    }
+    // Both are plain settings, so their debug form names every field
+    let settings = format!("{:?}\n{:?}", parsed.config, options);
+    write(Some(settings.as_bytes()));
+    hasher.write_u64(styling_hash);
+    hasher.finish()
+}
+
+/// Hashes the path, size and modification time of every file under `dir`, so a
+/// change to any of them is noticed without reading them.
+fn hash_dir_stamp(hasher: &mut FxHasher64, dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.is_dir() {
+            hash_dir_stamp(hasher, &entry.path());
+            continue;
+        }
+        hasher.write(entry.path().as_os_str().as_encoded_bytes());
+        hasher.write_u64(meta.len());
+        if let Ok(modified) = meta.modified() {
+            hasher.write_u128(modified.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos());
+        }
+    }
+}
+
+/// Content hash of an output body, used to version its URL.
+pub fn body_version(body: &[u8]) -> u64 {
+    section_fingerprint(Some(body))
+}
+
+/// Content-hashed file name of the output normally served as `stem.ext`, e.g.
+/// `style.3f2a1c0e9b8d7a65.css`.
+pub fn hashed_name(stem: &str, ext: &str, version: u64) -> String {
+    format!("{}.{:016x}.{}", stem, version, ext)
+}
+
+/// URL the page links an output by: its hashed name, or `stem.ext` with a `?v=`.
+fn asset_url(stem: &str, ext: &str, version: u64, hashed: bool) -> String {
+    if hashed {
+        format!("/{}", hashed_name(stem, ext, version))
+    } else {
+        format!("/{}.{}?v={}", stem, ext, version)
+    }
+}
+
+fn section_fingerprint(section: Option<&[u8]>) -> u64 {
+    let mut hasher = FxHasher64::default();
+    hasher.write(section.unwrap_or_default());
+    hasher.finish()
+}
+
+/// Durations of the individual `prepare()` phases, for diagnosing slow reloads.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PrepareTimings {
+    /// Reading the file and expanding its includes; zero when not read from disk.
+    pub read: Duration,
+    /// Splitting the source into sections; zero when not read from disk.
+    pub parse: Duration,
+    /// Styling compilation (CSS/SCSS/Stylus).
+    pub styling: Duration,
+    /// Script processing.
+    pub script: Duration,
+    /// Link, title and overlay injection into the markup.
+    pub injection: Duration,
+    /// Fingerprinting the content and its sections.
+    pub hashing: Duration,
+}
+
+impl PrepareTimings {
+    /// Wall-clock time of all phases; styling and script run in parallel.
+    pub fn total(&self) -> Duration {
+        self.read + self.parse + self.styling.max(self.script) + self.injection + self.hashing
+    }
+}
+
+impl PreparedContent {
+    /// The stylesheet minified; computed once per preparation.
+    pub fn minified_styling(&self) -> Option<Bytes> {
+        let minify = |css: &Bytes| Bytes::from(minify::css(&String::from_utf8_lossy(css)));
+        self.minified.styling.get_or_compute("minified stylesheet", || self.bodies.styling.as_ref().map(minify))
+    }
+
+    /// The script minified; computed once per preparation.
+    pub fn minified_script(&self) -> Option<Bytes> {
+        let minify = |js: &Bytes| Bytes::from(minify::js(&String::from_utf8_lossy(js)));
+        self.minified.script.get_or_compute("minified script", || self.bodies.script.as_ref().map(minify))
+    }
+
+    /// Fails with the collected compile errors, if any. Used by `--strict` to
+    /// turn errors that are otherwise only shown in the overlay into hard failures.
+    pub fn ensure_compiled(&self) -> Result<(), BreachError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(BreachError::Compile(self.errors.join("\n")))
+        }
+    }
 }
 
 impl Default for PreparedContent {
     fn default() -> Self {
         Self {
             parsed: ParsedContent::default(),
-            html_injected: None,
             fingerprint: 0,
+            errors: Vec::new(),
+            styling_errors: Vec::new(),
+            source_sizes: SectionSizes::default(),
+            prepared_at: SystemTime::UNIX_EPOCH,
+            timings: PrepareTimings::default(),
+            section_fingerprints: SectionFingerprints::default(),
+            bodies: ServedBodies::default(),
+            styling_source_hash: None,
+            content_key: None,
+            minified: MinifiedBodies::default(),
+        }
+    }
+}
+
+/// How the injected livereload client reacts to a reload notification.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReloadMode {
+    /// Refresh the page as soon as the content changes.
+    #[default]
+    Auto,
+    /// Show a "content changed" banner and wait for the user to click it.
+    Manual,
+}
+
+impl ReloadMode {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ReloadMode::Auto => "auto",
+            ReloadMode::Manual => "manual",
+        }
+    }
+}
+
+impl FromStr for ReloadMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(ReloadMode::Auto),
+            "manual" => Ok(ReloadMode::Manual),
+            other => Err(format!("unknown reload mode '{}' (expected 'auto' or 'manual')", other)),
+        }
+    }
+}
+
+/// What the injected livereload client reloads when the page is shown in an
+/// iframe. Outside of one, it always reloads the page.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReloadTarget {
+    /// Reload the page itself, which in an iframe is only the frame.
+    #[default]
+    Window,
+    /// Leave reloading to the embedding page: post it a
+    /// `{type: 'breach:reload', url}` message instead.
+    Parent,
+}
+
+impl ReloadTarget {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ReloadTarget::Window => "self",
+            ReloadTarget::Parent => "parent",
+        }
+    }
+}
+
+impl FromStr for ReloadTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "self" => Ok(ReloadTarget::Window),
+            "parent" => Ok(ReloadTarget::Parent),
+            other => Err(format!("unknown reload target '{}' (expected 'self' or 'parent')", other)),
+        }
+    }
+}
+
+/// Options that influence how parsed content is prepared for serving.
+#[derive(Clone, Debug)]
+pub struct PrepareOptions {
+    /// How the injected livereload client reacts to a reload message.
+    pub reload_mode: ReloadMode,
+    /// What the livereload client reloads when the page is in an iframe.
+    pub reload_target: ReloadTarget,
+    /// Failed reconnects in a row after which the livereload client stops
+    /// trying and shows that it is disconnected.
+    pub reconnect_attempts: u32,
+    /// Text of the plain reload message, `reload` by default.
+    pub reload_message: String,
+    /// Send reload messages as JSON (`{"type":"reload","fingerprint":"..."}`)
+    /// instead of the plain text, for custom clients.
+    pub reload_json: bool,
+    /// Serve the raw preprocessor source when compiling a styling section fails,
+    /// instead of dropping the section and reporting the error.
+    pub style_fallback: bool,
+    /// Never degrade on compile errors: no raw-source fallback and no reuse of the
+    /// previously compiled CSS. Errors are still collected for the overlay.
+    pub strict: bool,
+    /// Remove regular comments from compiled SCSS; `/*! ... */` comments are always kept.
+    pub strip_comments: bool,
+    /// Serve SCSS and Stylus sections as written instead of compiling them.
+    pub raw_styling: bool,
+    /// Inject the livereload client into the HTML. Disabled when output is
+    /// written out instead of served.
+    pub livereload: bool,
+    /// Largest source file, in bytes, that will be loaded.
+    pub max_file_size: u64,
+    /// Link the stylesheets and script by content-hashed file names so they can
+    /// be cached forever, instead of versioning them with `?v=`.
+    pub hashed_assets: bool,
+    /// Shared directories searched by SCSS `@use`/`@import` and by `¦include`
+    /// paths not found next to the including file.
+    pub include_dirs: Vec<PathBuf>,
+}
+
+/// Default for [`PrepareOptions::reload_message`].
+pub const DEFAULT_RELOAD_MESSAGE: &str = "reload";
+
+/// Default for [`PrepareOptions::reconnect_attempts`].
+pub const DEFAULT_RECONNECT_ATTEMPTS: u32 = 10;
+
+/// Default for [`PrepareOptions::max_file_size`]: 4 MiB.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+impl Default for PrepareOptions {
+    fn default() -> Self {
+        Self {
+            reload_mode: ReloadMode::default(),
+            reload_target: ReloadTarget::default(),
+            reconnect_attempts: DEFAULT_RECONNECT_ATTEMPTS,
+            reload_message: DEFAULT_RELOAD_MESSAGE.to_string(),
+            reload_json: false,
+            style_fallback: false,
+            strict: false,
+            strip_comments: false,
+            raw_styling: false,
+            livereload: true,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            hashed_assets: false,
+            include_dirs: Vec::new(),
         }
     }
 }
@@ -39,84 +503,565 @@ pub fn normalize_newlines(s: &str) -> String {
     s.replace("\r\n", "\n").replace('\r', "\n")
 }
 
+/// Strips what may precede a marker: whitespace (including NBSP) and the
+/// invisible characters editors and web pages leave behind (BOM, zero-width
+/// space/joiners, word joiner).
+pub(crate) fn trim_marker_prefix(line: &str) -> &str {
+    line.trim_start_matches(|c: char| {
+        c.is_whitespace() || matches!(c, '\u{feff}' | '\u{200b}'..='\u{200d}' | '\u{2060}')
+    })
+}
+
+/// Splits a marker line into the marker name and whatever follows it (the
+/// directives). The grammar is `¦` NAME [`@` VARIANT] [`:`] (end of line |
+/// whitespace DIRECTIVES), where NAME is ASCII alphanumeric and VARIANT may also
+/// contain `-` and `_`; `¦css:` and `¦css` are the same marker. Returns `None` if
+/// the line is not a marker, so `¦css2`, `¦css-foo` and `¦css×` are not `css`
+/// markers. The variant is read by [`marker_variant`].
+pub(crate) fn split_marker(line: &str) -> Option<(&str, &str)> {
+    // accepts only the special one which is extremely rarely used in any kind of text
+    let rest = trim_marker_prefix(line).strip_prefix('¦')?;
+    let ident_len = rest.bytes().take_while(|b| b.is_ascii_alphanumeric()).count();
+    let (ident, trailing) = rest.split_at(ident_len);
+    let trailing = match trailing.strip_prefix('@') {
+        Some(variant) => &variant[variant_name_len(variant)..],
+        None => trailing,
+    };
+    let trailing = trailing.strip_prefix(':').unwrap_or(trailing);
+    if !trailing.chars().next().is_none_or(char::is_whitespace) {
+        return None;
+    }
+    Some((ident, trailing))
+}
+
+/// Length of the variant name at the start of `s`.
+fn variant_name_len(s: &str) -> usize {
+    s.bytes().take_while(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_')).count()
+}
+
+/// Returns the lowercased variant a marker line names, e.g. `print` for
+/// `¦css@print`. See [`split_marker`].
+pub(crate) fn marker_variant(line: &str) -> Option<String> {
+    split_marker(line)?;
+    let rest = trim_marker_prefix(line).strip_prefix('¦')?;
+    let variant = rest.trim_start_matches(|c: char| c.is_ascii_alphanumeric()).strip_prefix('@')?;
+    let name = &variant[..variant_name_len(variant)];
+    (!name.is_empty()).then(|| name.to_ascii_lowercase())
+}
+
 /// Checks if a line starts with a section marker (¦) followed by the given name.
+/// Section names are ASCII and compared case-insensitively; see [`split_marker`]
+/// for the full marker grammar.
 pub fn starts_with_section_marker(line: &str, name: &str) -> bool {
-    let line = line.trim_start();
-    let mut chars = line.chars();
-    let first = match chars.next() {
-        Some(c) if c == '¦' => c, // accepts only the special one which is extremely rarely used in any kind of text
-        _ => return false,
+    split_marker(line).is_some_and(|(ident, _)| ident.eq_ignore_ascii_case(name))
+}
+
+/// The section a line of a .breach file belongs to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SectionType {
+    /// Before the first marker or after `¦end`; such lines are dropped.
+    None,
+    Markup,
+    Head,
+    CssStyling,
+    ScssStyling,
+    StylusStyling,
+    Script,
+    Config,
+    Data,
+}
+
+impl SectionType {
+    /// Short name used in diagnostics.
+    pub fn name(self) -> &'static str {
+        match self {
+            SectionType::None => "none",
+            SectionType::Markup => "html",
+            SectionType::Head => "head",
+            SectionType::CssStyling => "css",
+            SectionType::ScssStyling => "scss",
+            SectionType::StylusStyling => "stylus",
+            SectionType::Script => "script",
+            SectionType::Config => "config",
+            SectionType::Data => "data",
+        }
+    }
+}
+
+/// Returns the section a marker line opens, or `None` if the line is not a
+/// section marker. `¦end` closes the current section, opening [`SectionType::None`].
+pub fn marker_section(line: &str) -> Option<SectionType> {
+    const MARKERS: &[(&str, SectionType)] = &[
+        ("html", SectionType::Markup),
+        ("head", SectionType::Head),
+        ("css", SectionType::CssStyling),
+        ("scss", SectionType::ScssStyling),
+        ("stylus", SectionType::StylusStyling),
+        ("js", SectionType::Script),
+        ("javascript", SectionType::Script),
+        ("ts", SectionType::Script),
+        ("typescript", SectionType::Script),
+        ("config", SectionType::Config),
+        ("json", SectionType::Data),
+        ("data", SectionType::Data),
+        ("end", SectionType::None),
+    ];
+    // Split once per line rather than once per marker name; lines that don't start
+    // with `¦` are rejected after their leading whitespace, however long they are
+    let (ident, _) = split_marker(line)?;
+    MARKERS
+        .iter()
+        .find(|(name, _)| ident.eq_ignore_ascii_case(name))
+        .map(|(_, section)| *section)
+}
+
+/// Replaces every `¦include path` line with the contents of that file, recursively.
+/// Relative paths resolve against the directory of the file containing the
+/// directive; `path` is the file `source` was read from (`None` for stdin, which
+/// resolves against the working directory). A path not found there is looked up
+/// in `include_dirs`, in order. Each included file is appended to
+/// `dependencies` once, canonicalized.
+pub fn expand_includes(
+    source: &str,
+    path: Option<&Path>,
+    max_size: u64,
+    include_dirs: &[PathBuf],
+    dependencies: &mut Vec<PathBuf>,
+) -> Result<String, BreachError> {
+    let mut stack: Vec<PathBuf> = path.and_then(|p| p.canonicalize().ok()).into_iter().collect();
+    let base_dir = match stack.first().and_then(|p| p.parent()) {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+    expand_includes_in(source, &base_dir, max_size, include_dirs, &mut stack, dependencies)
+}
+
+fn expand_includes_in(
+    source: &str,
+    base_dir: &Path,
+    max_size: u64,
+    include_dirs: &[PathBuf],
+    stack: &mut Vec<PathBuf>,
+    dependencies: &mut Vec<PathBuf>,
+) -> Result<String, BreachError> {
+    // Cheap check so files without includes are returned untouched
+    if !source.contains("¦") {
+        return Ok(source.to_string());
+    }
+
+    let mut expanded = Vec::new();
+    for line in source.lines() {
+        if !starts_with_section_marker(line, "include") {
+            expanded.push(line.to_string());
+            continue;
+        }
+        let directives = split_marker(line).map_or("", |(_, directives)| directives);
+        let target = config::unquote(directives.trim());
+        let mut path = base_dir.join(target);
+        if !path.exists() {
+            if let Some(shared) = include_dirs.iter().map(|dir| dir.join(target)).find(|p| p.exists()) {
+                path = shared;
+            }
+        }
+        let canonical = path.canonicalize().map_err(|source| BreachError::Include {
+            path: path.clone(),
+            source,
+        })?;
+        if stack.contains(&canonical) {
+            return Err(BreachError::IncludeCycle(canonical));
+        }
+        let bytes = read_source_file(&canonical, max_size).map_err(|e| match e {
+            BreachError::Io(source) => BreachError::Include { path: path.clone(), source },
+            other => other,
+        })?;
+        if !dependencies.contains(&canonical) {
+            dependencies.push(canonical.clone());
+        }
+
+        let included = decode_source(&bytes);
+        let dir = canonical.parent().unwrap_or(base_dir).to_path_buf();
+        stack.push(canonical);
+        let nested = expand_includes_in(
+            &included,
+            &dir,
+            max_size,
+            include_dirs,
+            stack,
+            dependencies,
+        )?;
+        stack.pop();
+        expanded.push(nested);
+    }
+    Ok(expanded.join("\n"))
+}
+
+/// Languages a markup block can be written in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MarkupLang {
+    Html,
+}
+
+/// Compiles a single markup block to HTML. HTML blocks are used as-is.
+fn compile_markup_block(lang: MarkupLang, source: &str) -> String {
+    match lang {
+        MarkupLang::Html => source.to_string(),
+    }
+}
+
+/// Joins the lines of a section body without trailing whitespace on any line or
+/// trailing blank lines, so content differing only in invisible whitespace (e.g.
+/// an editor's final-newline habit) produces the same output and fingerprint.
+fn join_section_lines(lines: &[&str]) -> String {
+    let mut body = lines.iter().map(|line| line.trim_end()).collect::<Vec<_>>().join("\n");
+    body.truncate(body.trim_end().len());
+    body
+}
+
+/// Returns the value of a `key=value` or `key="value"` directive following the
+/// marker on a marker line.
+fn marker_directive<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let (_, mut rest) = split_marker(line)?;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let name_len = rest.find(['=', ' ', '\t']).unwrap_or(rest.len());
+        let (name, after) = rest.split_at(name_len);
+        let (value, remaining) = match after.strip_prefix('=') {
+            Some(v) => match v.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let inner = &v[1..];
+                    let end = inner.find(quote).unwrap_or(inner.len());
+                    (&inner[..end], inner.get(end + 1..).unwrap_or_default())
+                }
+                _ => {
+                    let end = v.find(char::is_whitespace).unwrap_or(v.len());
+                    (&v[..end], &v[end..])
+                }
+            },
+            None => ("", after),
+        };
+        if name == key {
+            return Some(value);
+        }
+        rest = remaining;
+    }
+}
+
+/// Turns a media query into a file-name-safe slug: `(max-width: 600px)` becomes
+/// `max-width-600px`.
+fn media_slug(media: &str) -> String {
+    let mut slug = String::new();
+    for c in media.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(slug.trim_end_matches('-').len());
+    if slug.is_empty() {
+        slug.push_str("media");
+    }
+    slug
+}
+
+/// The route of the variant `name`: its `route=` directive with a leading `/`
+/// and without a trailing one, or `/<name>`. `/` itself is the shared page and
+/// can't be taken by a variant.
+fn variant_route(name: &str, route: Option<&str>) -> String {
+    let default = format!("/{}", name);
+    let Some(route) = route else {
+        return default;
     };
-    let rest = &line[first.len_utf8()..];
-    let ident: String = rest.chars().take_while(|c| c.is_alphanumeric()).collect();
-    ident.eq_ignore_ascii_case(name)
+    let route = route.trim_matches('/');
+    if route.is_empty() {
+        tracing::warn!("Variant @{} can't be served at /, serving it at {}", name, default);
+        return default;
+    }
+    format!("/{}", route)
+}
+
+/// Lines of each styling language collected for one stylesheet.
+#[derive(Default)]
+struct StylingLines<'a> {
+    css: Vec<&'a str>,
+    scss: Vec<&'a str>,
+    stylus: Vec<&'a str>,
+    /// `¦css scoped` blocks, already rewritten to their markup block's attribute.
+    scoped: Vec<String>,
+}
+
+impl StylingLines<'_> {
+    /// Combines the languages into one styling string with type markers, in the
+    /// fixed order CSS, SCSS, Stylus. Returns `None` if all are empty.
+    fn combine(&self) -> Option<String> {
+        let mut styling_sections = Vec::new();
+        for (marker, lines) in [("CSS", &self.css), ("SCSS", &self.scss), ("STYLUS", &self.stylus)] {
+            let body = join_section_lines(lines);
+            if !body.trim().is_empty() {
+                styling_sections.push(format!("/* {} */\n{}\n/* EOF */", marker, body));
+            }
+        }
+        for scoped in &self.scoped {
+            styling_sections.push(format!("/* CSS */\n{}\n/* EOF */", scoped));
+        }
+        if styling_sections.is_empty() {
+            None
+        } else {
+            Some(styling_sections.join("\n\n"))
+        }
+    }
+}
+
+/// Where a section is in the source text, for mapping served output back to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionSpan {
+    pub kind: SectionType,
+    /// Byte range from the start of the marker line to the start of the next
+    /// marker line, or the end of the source. Front-matter covers its delimiters.
+    pub range: Range<usize>,
+}
+
+/// Like [`parse_breach_content`], also returning the span of every section in
+/// `content`. See [`section_spans`].
+pub fn parse_breach_content_with_spans(content: &str) -> (ParsedContent, Vec<SectionSpan>) {
+    (parse_breach_content(content), section_spans(content))
+}
+
+/// The span of every section in `content`, in order. Offsets are into
+/// `content` as given (a leading BOM and `\r\n` newlines included), so they
+/// match the file for sources read without includes. Lines the parser drops
+/// (after `¦end`, or before the first marker) belong to no span.
+pub fn section_spans(content: &str) -> Vec<SectionSpan> {
+    let start = content.len() - content.trim_start_matches('\u{feff}').len();
+    let (front_matter, body) = config::split_front_matter(&content[start..]);
+    let mut offset = content.len() - body.len();
+    let mut spans = Vec::new();
+    if front_matter.is_some() {
+        spans.push(SectionSpan { kind: SectionType::Config, range: start..offset });
+    }
+    let mut open: Option<(SectionType, usize)> = None;
+    for line in body.split_inclusive('\n') {
+        if let Some(kind) = marker_section(line) {
+            if let Some((kind, from)) = open.take() {
+                spans.push(SectionSpan { kind, range: from..offset });
+            }
+            open = (kind != SectionType::None).then_some((kind, offset));
+        }
+        offset += line.len();
+    }
+    if let Some((kind, from)) = open {
+        spans.push(SectionSpan { kind, range: from..offset });
+    }
+    spans
 }
 
 /// Parses the content of a .breach file into structured sections using generic content types.
 pub fn parse_breach_content(content: &str) -> ParsedContent {
-    let mut markup_lines = Vec::new();
-    let mut css_styling_lines = Vec::new();
-    let mut scss_styling_lines = Vec::new();
+    // Each markup marker opens a new block so blocks in different languages can be
+    // compiled separately and concatenated in authoring order
+    let mut markup_blocks: Vec<(MarkupLang, Vec<&str>)> = Vec::new();
+    // Styling without a media directive, and per media query in order of appearance
+    let mut styling_lines = StylingLines::default();
+    let mut media_lines: Vec<(&str, StylingLines)> = Vec::new();
+    let mut cur_media: Option<usize> = None;
+    // Styling of each named variant with its route, in order of appearance
+    let mut variant_lines: Vec<(String, Option<&str>, StylingLines)> = Vec::new();
+    let mut cur_variant: Option<usize> = None;
+    // Scoped CSS blocks with their media group and the markup block they style
+    let mut scoped_blocks: Vec<(Option<usize>, usize, Vec<&str>)> = Vec::new();
+    let mut cur_scoped: Option<usize> = None;
+    // `¦js module` (or `type=module`) on any script marker makes the script a module
+    let mut script_module = false;
     let mut script_lines = Vec::new();
+    let mut head_lines = Vec::new();
+    let mut data_lines = Vec::new();
+    let mut config_lines = Vec::new();
 
-    #[derive(Copy, Clone, PartialEq, Eq)]
-    enum SectionType {
-        None,
-        Markup,
-        CssStyling,
-        ScssStyling,
-        Script,
-    }
     let mut cur = SectionType::None;
 
     let normalized = normalize_newlines(content.trim_start_matches('\u{feff}'));
+    let (front_matter, body) = config::split_front_matter(&normalized);
+    // Front-matter comes first so a `¦config` section can override it. Config lines
+    // keep their line number in the file for diagnostics; the front-matter starts
+    // after its opening delimiter on line 1
+    if let Some(front_matter) = front_matter {
+        config_lines.extend(front_matter.lines().enumerate().map(|(i, line)| (i + 2, line)));
+    }
+    let body_start = normalized[..normalized.len() - body.len()].lines().count() + 1;
 
-    for line in normalized.lines() {
-        if starts_with_section_marker(line, "html") {
-            cur = SectionType::Markup;
-            continue;
-        }
-        if starts_with_section_marker(line, "css") {
-            cur = SectionType::CssStyling;
-            continue;
-        }
-        if starts_with_section_marker(line, "scss") {
-            cur = SectionType::ScssStyling;
+    for (index, line) in body.lines().enumerate() {
+        if let Some(section) = marker_section(line) {
+            cur = section;
+            cur_scoped = None;
+            cur_variant = None;
+            let variant = marker_variant(line);
+            let is_styling = matches!(
+                section,
+                SectionType::CssStyling | SectionType::ScssStyling | SectionType::StylusStyling
+            );
+            if variant.is_some() && !is_styling {
+                tracing::warn!("Variants are only supported on styling sections, ignoring it on {:?}", line.trim());
+            }
+            match section {
+                SectionType::Markup => markup_blocks.push((MarkupLang::Html, Vec::new())),
+                _ if is_styling && variant.is_some() => {
+                    // A variant's styling only applies to its own page, so media
+                    // and scoping, which target the shared page, don't combine with it
+                    if marker_directive(line, "media").is_some() || marker_directive(line, "scoped").is_some() {
+                        tracing::warn!("`media` and `scoped` don't apply to variants, ignoring them on {:?}", line.trim());
+                    }
+                    let name = variant.unwrap_or_default();
+                    let route = marker_directive(line, "route").map(str::trim).filter(|r| !r.is_empty());
+                    let i = match variant_lines.iter().position(|(n, _, _)| *n == name) {
+                        Some(i) => i,
+                        None => {
+                            variant_lines.push((name, None, StylingLines::default()));
+                            variant_lines.len() - 1
+                        }
+                    };
+                    if route.is_some() {
+                        variant_lines[i].1 = route;
+                    }
+                    cur_variant = Some(i);
+                    cur_media = None;
+                }
+                SectionType::CssStyling | SectionType::ScssStyling | SectionType::StylusStyling => {
+                    cur_media = marker_directive(line, "media")
+                        .map(str::trim)
+                        .filter(|media| !media.is_empty() && *media != "all")
+                        .map(|media| match media_lines.iter().position(|(m, _)| *m == media) {
+                            Some(i) => i,
+                            None => {
+                                media_lines.push((media, StylingLines::default()));
+                                media_lines.len() - 1
+                            }
+                        });
+                    if marker_directive(line, "scoped").is_some() {
+                        if section == SectionType::CssStyling {
+                            // A scoped block styles the markup block it follows
+                            if markup_blocks.is_empty() {
+                                tracing::warn!(
+                                    "{:?} comes before any ¦html block, scoping it to the first one",
+                                    line.trim()
+                                );
+                            }
+                            let block = markup_blocks.len().saturating_sub(1);
+                            scoped_blocks.push((cur_media, block, Vec::new()));
+                            cur_scoped = Some(scoped_blocks.len() - 1);
+                        } else {
+                            tracing::warn!("`scoped` is only supported on ¦css sections, ignoring it on {:?}", line.trim());
+                        }
+                    }
+                }
+                SectionType::Script => {
+                    script_module |= marker_directive(line, "module").is_some()
+                        || marker_directive(line, "type").is_some_and(|t| t.eq_ignore_ascii_case("module"));
+                }
+                _ => {}
+            }
             continue;
         }
-        if starts_with_section_marker(line, "js") || starts_with_section_marker(line, "ts") || starts_with_section_marker(line, "typescript")
-        {
-            cur = SectionType::Script;
+        if let Some(i) = cur_scoped {
+            scoped_blocks[i].2.push(line);
             continue;
         }
         match cur {
-            SectionType::Markup => markup_lines.push(line),
-            SectionType::CssStyling => css_styling_lines.push(line),
-            SectionType::ScssStyling => scss_styling_lines.push(line),
+            SectionType::Markup => {
+                if let Some((_, lines)) = markup_blocks.last_mut() {
+                    lines.push(line);
+                }
+            }
+            SectionType::CssStyling | SectionType::ScssStyling | SectionType::StylusStyling => {
+                let target = match (cur_variant, cur_media) {
+                    (Some(i), _) => &mut variant_lines[i].2,
+                    (None, Some(i)) => &mut media_lines[i].1,
+                    (None, None) => &mut styling_lines,
+                };
+                match cur {
+                    SectionType::CssStyling => target.css.push(line),
+                    SectionType::ScssStyling => target.scss.push(line),
+                    _ => target.stylus.push(line),
+                }
+            }
             SectionType::Script => script_lines.push(line),
+            SectionType::Head => head_lines.push(line),
+            SectionType::Data => data_lines.push(line),
+            SectionType::Config => config_lines.push((body_start + index, line)),
             SectionType::None => {}
         }
     }
 
-    let markup = markup_lines.join("\n");
-    let css_styling = css_styling_lines.join("\n");
-    let scss_styling = scss_styling_lines.join("\n");
-    let script = script_lines.join("\n");
-
-    // Combine styling sections with markers
-    let mut styling_sections = Vec::new();
-    if !css_styling.trim().is_empty() {
-        styling_sections.push(format!("/* CSS */\n{}\n/* EOF */", css_styling));
+    let markup = markup_blocks
+        .iter()
+        .enumerate()
+        .map(|(i, (lang, lines))| {
+            let html = compile_markup_block(*lang, &join_section_lines(lines));
+            if scoped_blocks.iter().any(|(_, block, _)| *block == i) {
+                scope::scope_html(&html, &scope::attribute(i))
+            } else {
+                html
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    for (media, block, lines) in &scoped_blocks {
+        let css = join_section_lines(lines);
+        if css.trim().is_empty() {
+            continue;
+        }
+        if markup_blocks.get(*block).is_none_or(|(_, lines)| lines.iter().all(|l| l.trim().is_empty())) {
+            tracing::warn!("A `¦css scoped` block has no markup to scope to, so its rules match nothing");
+        }
+        let target = match media {
+            Some(i) => &mut media_lines[*i].1,
+            None => &mut styling_lines,
+        };
+        target.scoped.push(scope::scope_css(&css, &scope::attribute(*block)));
     }
-    if !scss_styling.trim().is_empty() {
-        styling_sections.push(format!("/* SCSS */\n{}\n/* EOF */", scss_styling));
+    let script = join_section_lines(&script_lines);
+    let head = join_section_lines(&head_lines);
+    let data = join_section_lines(&data_lines);
+
+    let combined_styling = styling_lines.combine();
+    let mut media_styling: Vec<MediaStyling> = Vec::new();
+    for (media, lines) in &media_lines {
+        let Some(styling) = lines.combine() else {
+            continue;
+        };
+        // Distinct queries can reduce to the same slug, e.g. `print` and `(print)`
+        let base = media_slug(media);
+        let mut slug = base.clone();
+        let mut n = 2;
+        while media_styling.iter().any(|m| m.slug == slug) {
+            slug = format!("{}-{}", base, n);
+            n += 1;
+        }
+        media_styling.push(MediaStyling {
+            media: media.to_string(),
+            slug,
+            styling,
+        });
     }
 
-    let combined_styling = if styling_sections.is_empty() {
-        None
-    } else {
-        Some(styling_sections.join("\n\n"))
-    };
+    let variants: Vec<StylingVariant> = variant_lines
+        .iter()
+        .filter_map(|(name, route, lines)| {
+            Some(StylingVariant {
+                route: variant_route(name, *route),
+                name: name.clone(),
+                styling: lines.combine()?,
+            })
+        })
+        .collect();
+
+    let mut config = config::parse_config(config_lines);
+    if script_module {
+        config.script_type = ScriptType::Module;
+        config.lines.remove("script_type");
+    }
 
     let parsed_content = ParsedContent {
         markup: if markup.trim().is_empty() {
@@ -130,9 +1075,18 @@ pub fn parse_breach_content(content: &str) -> ParsedContent {
         } else {
             Some(script)
         },
+        head: if head.trim().is_empty() { None } else { Some(head) },
+        data: if data.trim().is_empty() { None } else { Some(data) },
+        config,
+        source_path: None,
+        dependencies: Vec::new(),
+        media_styling,
+        variants,
+        favicon: None,
+        load_error: None,
     };
 
-    tracing::info!("ParsedContent: Markup present: {}, Styling present: {}, Script present: {}",
+    tracing::debug!("ParsedContent: Markup present: {}, Styling present: {}, Script present: {}",
         parsed_content.markup.is_some(),
         parsed_content.styling.is_some(),
         parsed_content.script.is_some()
@@ -141,21 +1095,101 @@ pub fn parse_breach_content(content: &str) -> ParsedContent {
     parsed_content
 }
 
-/// Compiles SCSS content to CSS using the grass compiler.
-/// Returns the compiled CSS or an error if compilation fails.
-pub fn compile_scss_to_css(scss_content: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let options = grass::Options::default();
-    match grass::from_string(scss_content.to_string(), &options) {
+/// Removes `/* ... */` comments from CSS, except `/*! ... */` comments (license
+/// headers by convention). Quoted strings are copied untouched.
+fn strip_css_comments(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(pos) = rest.find(['/', '"', '\'']) {
+        let (before, from) = rest.split_at(pos);
+        out.push_str(before);
+        let quote = from.as_bytes()[0];
+        if quote != b'/' {
+            // Copy the string literal, honoring backslash escapes
+            let mut end = 1;
+            let bytes = from.as_bytes();
+            while end < bytes.len() && bytes[end] != quote {
+                end += if bytes[end] == b'\\' { 2 } else { 1 };
+            }
+            let end = (end + 1).min(from.len());
+            out.push_str(&from[..end]);
+            rest = &from[end..];
+        } else if from.starts_with("/*") && !from.starts_with("/*!") {
+            rest = match from[2..].find("*/") {
+                Some(close) => &from[close + 4..],
+                None => "",
+            };
+        } else {
+            out.push('/');
+            rest = &from[1..];
+        }
+    }
+    out.push_str(rest);
+    // Drop the lines the removed comments leave empty
+    out.lines().filter(|line| !line.trim().is_empty()).collect::<Vec<_>>().join("\n")
+}
+
+/// Runs `compile`, turning a panic into [`ScssError::Panic`]. A panic in grass on
+/// pathological input would otherwise take the watcher thread down with it and
+/// end live reload. Nothing outlives the call, so a half-finished compilation
+/// can't be observed.
+fn catch_compiler_panic<T>(compile: impl FnOnce() -> T) -> Result<T, ScssError> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(compile)).map_err(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        tracing::error!("grass panicked while compiling SCSS: {}", message);
+        ScssError::Panic(message)
+    })
+}
+
+/// Compiles SCSS content to CSS using the grass compiler, resolving imports
+/// against `load_paths` as well as the working directory.
+/// Regular comments are removed when `strip_comments` is set; `/*! ... */`
+/// comments are always kept. Returns the compiled CSS or an error if compilation fails.
+pub fn compile_scss_to_css(scss_content: &str, strip_comments: bool, load_paths: &[PathBuf]) -> Result<String, ScssError> {
+    let options = grass::Options::default().load_paths(load_paths);
+    let compiled = catch_compiler_panic(|| grass::from_string(scss_content.to_string(), &options))?;
+    match compiled {
         Ok(css) => {
-            tracing::info!("SCSS compilation successful, {} bytes -> {} bytes",
+            let css = if strip_comments { strip_css_comments(&css) } else { css };
+            tracing::debug!("SCSS compilation successful, {} bytes -> {} bytes",
                 scss_content.len(), css.len());
             Ok(css)
         }
-        Err(e) => {
-            tracing::error!("SCSS compilation failed: {}", e);
-            Err(e.into())
-        }
+        Err(e) => Err(ScssError::from_message(e.to_string())),
+    }
+}
+
+/// Compiles Stylus content to CSS by piping it through the `stylus` executable,
+/// as there is no Rust implementation of the compiler.
+pub fn compile_stylus_to_css(stylus_content: &str) -> Result<String, StylusError> {
+    let mut child = Command::new("stylus")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(StylusError::NotInstalled)?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(stylus_content.as_bytes())
+            .map_err(|e| StylusError::Failed(e.to_string()))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| StylusError::Failed(e.to_string()))?;
+    if !output.status.success() {
+        return Err(StylusError::Failed(String::from_utf8_lossy(&output.stderr).trim().to_string()));
     }
+
+    let css = String::from_utf8_lossy(&output.stdout).to_string();
+    tracing::debug!("Stylus compilation successful, {} bytes -> {} bytes",
+        stylus_content.len(), css.len());
+    Ok(css)
 }
 
 /// Finds the case-insensitive position of a substring within a string.
@@ -188,50 +1222,54 @@ fn extract_and_remove_title(html: &str) -> (String, Option<String>) {
     (result, title_content)
 }
 
-/// Injects a CSS link tag into the HTML at the appropriate location.
-/// Returns the modified HTML.
-fn inject_css_link(html: &str, link_tag: &str, title_content: Option<&str>) -> String {
+/// Returns true if `html` declares a `<meta name="viewport">`.
+fn has_viewport_meta(html: &str) -> bool {
+    ["name=\"viewport\"", "name='viewport'", "name=viewport"]
+        .iter()
+        .any(|needle| find_case_insensitive(html, needle).is_some())
+}
+
+/// Removes `<meta charset=...>` tags from `html`.
+fn remove_meta_charset(html: &str) -> String {
+    let mut result = html.to_string();
+    while let Some(start) = find_case_insensitive(&result, "<meta charset") {
+        let end = result[start..].find('>').map_or(result.len(), |end| start + end + 1);
+        result.replace_range(start..end, "");
+    }
+    result
+}
+
+/// Injects tags (stylesheet links, title) into the document head, creating a head
+/// when the markup has none. Returns the modified HTML.
+fn inject_head_tags(html: &str, tags: &[String]) -> String {
+    let block: String = tags.iter().map(|tag| format!("\n    {}", tag)).collect();
     if let Some(head_end) = find_case_insensitive(html, "</head>") {
         let mut result = html.to_string();
-        result.insert_str(head_end, &format!("\n    {}", link_tag));
+        result.insert_str(head_end, &block);
         result
     } else if let Some(head_start) = find_case_insensitive(html, "<head>") {
         let mut result = html.to_string();
         let insert_at = head_start + "<head>".len();
-        result.insert_str(insert_at, &format!("\n    {}", link_tag));
+        result.insert_str(insert_at, &block);
         result
     } else if let Some(html_open) = find_case_insensitive(html, "<html>") {
         let mut result = html.to_string();
         let insert_at = html_open + "<html>".len();
-        let head_content = if let Some(tc) = title_content {
-            format!(
-                "<head>\n    {}\n    <title>{}</title>\n</head>",
-                link_tag, tc
-            )
-        } else {
-            format!("<head>\n    {}\n</head>", link_tag)
-        };
-        result.insert_str(insert_at, &format!("\n{}", head_content));
+        result.insert_str(insert_at, &format!("\n<head>{}\n</head>", block));
         result
+    } else if tags.iter().any(|tag| find_case_insensitive(tag, "<meta charset").is_some()) {
+        format!("<head>{}\n</head>\n{}", block, html)
     } else {
-        let head_content = if let Some(tc) = title_content {
-            format!(
-                "<head>\n    <meta charset=\"utf-8\">\n    {}\n    <title>{}</title>\n</head>\n{}",
-                link_tag, tc, html
-            )
-        } else {
-            format!(
-                "<head>\n    <meta charset=\"utf-8\">\n    {}\n</head>\n{}",
-                link_tag, html
-            )
-        };
-        head_content
+        format!(
+            "<head>\n    <meta charset=\"utf-8\">{}\n</head>\n{}",
+            block, html
+        )
     }
 }
 
 /// Injects a JS script tag into the HTML at the appropriate location.
 /// Returns the modified HTML.
-fn inject_js_script(html: &str, script_tag: &str) -> String {
+pub fn inject_js_script(html: &str, script_tag: &str) -> String {
     if let Some(body_end) = find_case_insensitive(html, "</body>") {
         let mut result = html.to_string();
         result.insert_str(body_end, &format!("\n    {}", script_tag));
@@ -241,73 +1279,293 @@ fn inject_js_script(html: &str, script_tag: &str) -> String {
     }
 }
 
-/// Injects CSS and JS link tags into the HTML content, handling various HTML structures.
-/// Preserves the title if present and adds links in the appropriate locations.
-/// Also injects livereload WebSocket script.
-pub fn inject_links_once(html: &str, has_css: bool, has_js: bool, fingerprint: u64) -> String {
-    let (mut result, title_content) = extract_and_remove_title(html);
+/// Version of the `/ws` messages, announced to clients as `hello <version>`.
+// Bump it whenever clients must understand new messages; an injected client seeing
+// another version reloads the page to get the current one
+pub const LIVERELOAD_PROTOCOL: u32 = 1;
 
-    if has_css {
-        let link_tag = format!(
-            r#"<link rel="stylesheet" href="/style.css?v={}">"#,
-            fingerprint
-        );
-        result = inject_css_link(&result, &link_tag, title_content.as_deref());
-    }
-
-    if has_js {
-        let script_tag = format!(r#"<script src="/script.js?v={}"></script>"#, fingerprint);
-        result = inject_js_script(&result, &script_tag);
-    }
-
-    // Inject livereload WebSocket script
-    let livereload_script = r#"<script>
+/// Client-side livereload script injected into every served page.
+const LIVERELOAD_SCRIPT: &str = r#"<script>
 (function() {
+    var reloadMode = '__BREACH_RELOAD_MODE__';
+    var reloadTarget = '__BREACH_RELOAD_TARGET__';
+    var protocolVersion = '__BREACH_PROTOCOL__';
+    var reloadMessage = __BREACH_RELOAD_MESSAGE__;
     console.log('B-REACH: Initializing live reload...');
-    var ws = new WebSocket('ws://' + window.location.host + '/ws');
-    console.log('B-REACH: Attempting to connect to WebSocket at:', 'ws://' + window.location.host + '/ws');
 
-    ws.onopen = function(event) {
-        console.log('B-REACH: Live reload WebSocket connection established');
-    };
-
-    ws.onmessage = function(event) {
-        console.log('B-REACH: Received WebSocket message:', event.data);
-        if (event.data === 'reload') {
-            console.log('B-REACH: Reload signal received, refreshing page...');
-            window.location.reload();
+    // In an iframe, location.reload() only reloads the frame. With the parent as
+    // target, the embedding page is told instead and decides what to reload
+    function reloadPage() {
+        if (reloadTarget === 'parent' && window.parent !== window) {
+            console.log('B-REACH: Asking the embedding page to reload');
+            window.parent.postMessage({ type: 'breach:reload', url: window.location.href }, '*');
         } else {
-            console.log('B-REACH: Unknown message received:', event.data);
+            window.location.reload();
         }
-    };
+    }
 
-    ws.onclose = function(event) {
-        console.log('B-REACH: Live reload WebSocket connection closed', {
-            code: event.code,
-            reason: event.reason,
-            wasClean: event.wasClean
-        });
-    };
+    function showReloadBanner() {
+        if (document.getElementById('breach-reload-banner')) {
+            return;
+        }
+        var banner = document.createElement('div');
+        banner.id = 'breach-reload-banner';
+        banner.textContent = 'Content changed \u2014 click to reload';
+        banner.style.cssText = 'position:fixed;right:16px;bottom:16px;z-index:2147483647;' +
+            'padding:10px 14px;border-radius:6px;background:#212121;color:#fff;' +
+            'font:14px system-ui,sans-serif;box-shadow:0 2px 8px rgba(0,0,0,.4);cursor:pointer';
+        banner.onclick = function() {
+            reloadPage();
+        };
+        document.body.appendChild(banner);
+    }
 
-    ws.onerror = function(error) {
-        console.error('B-REACH: Live reload WebSocket connection error:', error);
-        console.error('B-REACH: This may indicate the server is not running or WebSocket endpoint is unavailable');
-    };
+    // Reloads come as the configured text or, with --reload-json, as
+    // {"type":"reload",...}; both are understood so either setting works
+    function isReload(data) {
+        if (data === reloadMessage) {
+            return true;
+        }
+        if (data.charAt(0) !== '{') {
+            return false;
+        }
+        try {
+            return JSON.parse(data).type === 'reload';
+        } catch (e) {
+            return false;
+        }
+    }
 
-    // Log connection attempt every 5 seconds if not connected
-    var connectionCheck = setInterval(function() {
-        if (ws.readyState === WebSocket.CONNECTING) {
-            console.log('B-REACH: Still attempting to connect to live reload WebSocket...');
-        } else if (ws.readyState === WebSocket.CLOSED) {
-            console.warn('B-REACH: WebSocket connection is closed, attempting to reconnect...');
-            clearInterval(connectionCheck);
-        } else {
-            clearInterval(connectionCheck);
+    // Reconnects when the connection drops (e.g. the server restarting), waiting
+    // a little longer after each failure. After maxAttempts failures in a row it
+    // gives up and says so on the page rather than going quiet
+    var maxAttempts = __BREACH_RECONNECT_ATTEMPTS__;
+    var attempts = 0;
+
+    function showDisconnected() {
+        if (document.getElementById('breach-disconnected')) {
+            return;
+        }
+        var badge = document.createElement('div');
+        badge.id = 'breach-disconnected';
+        badge.textContent = 'Disconnected from breach \u2014 live reload is off. Click to retry';
+        badge.style.cssText = 'position:fixed;left:16px;bottom:16px;z-index:2147483647;' +
+            'padding:6px 10px;border-radius:6px;background:#b71c1c;color:#fff;' +
+            'font:12px system-ui,sans-serif;box-shadow:0 2px 8px rgba(0,0,0,.4);cursor:pointer';
+        badge.onclick = function() {
+            badge.parentNode.removeChild(badge);
+            attempts = 0;
+            connect();
+        };
+        (document.body || document.documentElement).appendChild(badge);
+    }
+
+    function connect() {
+        // The server compares the fingerprint this page was served with against the
+        // current one, so a change made while the page loaded (or while it was
+        // disconnected) still reloads it
+        var url = 'ws://' + window.location.host + '/ws?fingerprint=__BREACH_FINGERPRINT__';
+        if (attempts === 0) {
+            console.log('B-REACH: Attempting to connect to WebSocket at:', 'ws://' + window.location.host + '/ws');
         }
-    }, 5000);
+        var ws = new WebSocket(url);
+
+        ws.onopen = function(event) {
+            attempts = 0;
+            console.log('B-REACH: Live reload WebSocket connection established');
+        };
+
+        ws.onmessage = function(event) {
+            console.log('B-REACH: Received WebSocket message:', event.data);
+            if (event.data.indexOf('hello ') === 0) {
+                // A page open across a server upgrade runs an outdated client; reloading
+                // fetches the one matching the server's messages
+                var serverVersion = event.data.slice('hello '.length);
+                if (serverVersion !== protocolVersion) {
+                    console.log('B-REACH: Live reload protocol changed (' + protocolVersion + ' -> ' + serverVersion + '), reloading client...');
+                    window.location.reload();
+                }
+            } else if (isReload(event.data)) {
+                if (reloadMode === 'manual') {
+                    console.log('B-REACH: Reload signal received, waiting for user to reload');
+                    showReloadBanner();
+                } else {
+                    console.log('B-REACH: Reload signal received, refreshing page...');
+                    reloadPage();
+                }
+            } else {
+                console.log('B-REACH: Unknown message received:', event.data);
+            }
+        };
+
+        ws.onclose = function(event) {
+            if (attempts === 0) {
+                console.log('B-REACH: Live reload WebSocket connection closed', {
+                    code: event.code,
+                    reason: event.reason,
+                    wasClean: event.wasClean
+                });
+            }
+            if (attempts >= maxAttempts) {
+                console.warn('B-REACH: Server unreachable, giving up on live reload after ' + attempts + ' reconnect attempt(s)');
+                showDisconnected();
+                return;
+            }
+            attempts++;
+            setTimeout(connect, Math.min(1000 * attempts, 5000));
+        };
+
+        ws.onerror = function(error) {
+            // Failed reconnects are reported once, when giving up
+            if (attempts === 0) {
+                console.error('B-REACH: Live reload WebSocket connection error:', error);
+                console.error('B-REACH: This may indicate the server is not running or WebSocket endpoint is unavailable');
+            }
+        };
+    }
+
+    connect();
 })();
 </script>"#;
-    result = inject_js_script(&result, livereload_script);
+
+/// Encodes `s` as a JSON string literal.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Builds the livereload client script for a page showing the content with
+/// `fingerprint`, following the reload settings of `options`.
+pub fn livereload_script(options: &PrepareOptions, fingerprint: u64) -> String {
+    // `<` is escaped so the message can't close the script element
+    let reload_message = json_string(&options.reload_message).replace('<', "\\u003c");
+    LIVERELOAD_SCRIPT
+        .replace("__BREACH_RELOAD_MODE__", options.reload_mode.as_str())
+        .replace("__BREACH_RELOAD_TARGET__", options.reload_target.as_str())
+        .replace("__BREACH_RELOAD_MESSAGE__", &reload_message)
+        .replace("__BREACH_FINGERPRINT__", &fingerprint.to_string())
+        .replace("__BREACH_RECONNECT_ATTEMPTS__", &options.reconnect_attempts.to_string())
+        .replace("__BREACH_PROTOCOL__", &LIVERELOAD_PROTOCOL.to_string())
+}
+
+/// What `inject_links_once` links into a page besides the livereload client.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PageAssets<'a> {
+    /// Fingerprints of the stylesheet and script (`None` when absent), used as
+    /// their `?v=` so an asset URL only changes with that asset, not with the
+    /// markup or the injected livereload client.
+    pub css_version: Option<u64>,
+    pub js_version: Option<u64>,
+    pub media_styling: &'a [ServedMedia],
+    /// The `¦head` section, if any.
+    pub head: Option<&'a str>,
+}
+
+/// Injects CSS and JS link tags into the HTML content, handling various HTML structures.
+/// Preserves the title if present (falling back to the configured title) and adds links
+/// in the appropriate locations, skipping assets the markup already references.
+/// Also injects livereload WebSocket script.
+///
+/// `fingerprint` is that of the whole content, reported by the livereload
+/// client when it connects.
+pub fn inject_links_once(
+    html: &str,
+    assets: PageAssets,
+    config: &BreachConfig,
+    fingerprint: u64,
+    options: &PrepareOptions,
+) -> String {
+    let PageAssets { css_version, js_version, media_styling, head } = assets;
+    // Authors who link the assets themselves don't get a second copy injected
+    let css_version = css_version.filter(|_| !html.contains("/style.css"));
+    let media_links: Vec<String> = media_styling
+        .iter()
+        .filter(|m| !html.contains(&format!("/style.{}.css", m.slug)))
+        .map(|m| {
+            format!(
+                r#"<link rel="stylesheet" href="{}" media="{}">"#,
+                asset_url(&format!("style.{}", m.slug), "css", body_version(&m.body), options.hashed_assets),
+                escape_html(&m.media)
+            )
+        })
+        .collect();
+    let js_version = js_version.filter(|_| !html.contains("/script.js"));
+
+    let (mut result, title_content) = extract_and_remove_title(html);
+
+    // A title or charset in `¦head` only counts if the markup has none of its own,
+    // so the page never ends up with two
+    let (head, head_title) = match head {
+        Some(head) => {
+            let (head, title) = extract_and_remove_title(head);
+            let head = if find_case_insensitive(&result, "<meta charset").is_some() {
+                remove_meta_charset(&head)
+            } else {
+                head
+            };
+            (Some(head), title)
+        }
+        None => (None, None),
+    };
+
+    // The author's title always wins over the configured one
+    let title = title_content
+        .or(head_title)
+        .or_else(|| config.title.as_deref().map(escape_html));
+
+    let mut head_tags = Vec::new();
+    let has_viewport = has_viewport_meta(&result) || head.as_deref().is_some_and(has_viewport_meta);
+    if let Some(content) = config.viewport.content().filter(|_| !has_viewport) {
+        head_tags.push(format!(r#"<meta name="viewport" content="{}">"#, escape_html(content)));
+    }
+    if let Some(head) = head.as_deref().map(str::trim).filter(|h| !h.is_empty()) {
+        head_tags.push(head.lines().map(str::trim).collect::<Vec<_>>().join("\n    "));
+    }
+    if let Some(version) = css_version {
+        head_tags.push(format!(
+            r#"<link rel="stylesheet" href="{}">"#,
+            asset_url("style", "css", version, options.hashed_assets)
+        ));
+    }
+    head_tags.extend(media_links);
+    if let Some(title) = title {
+        head_tags.push(format!("<title>{}</title>", title));
+    }
+    if !head_tags.is_empty() {
+        result = inject_head_tags(&result, &head_tags);
+    }
+
+    if let Some(version) = js_version {
+        let script_type = match config.script_type {
+            ScriptType::Classic => "",
+            ScriptType::Module => r#" type="module""#,
+        };
+        let script_tag = format!(
+            r#"<script{} src="{}"></script>"#,
+            script_type,
+            asset_url("script", "js", version, options.hashed_assets)
+        );
+        result = inject_js_script(&result, &script_tag);
+    }
+
+    // Inject livereload WebSocket script
+    if options.livereload {
+        let livereload_script = livereload_script(options, fingerprint);
+        result = inject_js_script(&result, &livereload_script);
+    }
 
     result
 }
@@ -316,10 +1574,123 @@ pub fn inject_links_once(html: &str, has_css: bool, has_js: bool, fingerprint: u
 
 
 
-/// Processes styling content with markers and compiles as needed.
-/// Returns the final CSS content.
-fn process_styling_content(styling_content: &str) -> String {
+/// Escapes text for safe inclusion in HTML element content or attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Replaces `{{ path }}` placeholders in `markup` with values from `data`, looked
+/// up by dotted path (`user.name`, `items.0.title`). Strings are HTML-escaped and
+/// other values written as JSON; a placeholder whose path doesn't resolve is left
+/// as it is.
+fn interpolate(markup: &str, data: &serde_json::Value) -> String {
+    let mut out = String::with_capacity(markup.len());
+    let mut rest = markup;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let end = start + 2 + len + 2;
+        let path = rest[start + 2..end - 2].trim();
+        out.push_str(&rest[..start]);
+        match lookup_data(data, path) {
+            Some(serde_json::Value::String(text)) => out.push_str(&escape_html(text)),
+            Some(serde_json::Value::Null) => {}
+            Some(value) => out.push_str(&escape_html(&value.to_string())),
+            None => {
+                tracing::warn!("No data for {{{{ {} }}}}, leaving it in place", path);
+                out.push_str(&rest[start..end]);
+            }
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Resolves a dotted path into `data`; numeric segments index arrays.
+fn lookup_data<'a>(data: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    if path.is_empty() {
+        return None;
+    }
+    path.split('.').try_fold(data, |value, key| match value {
+        serde_json::Value::Object(map) => map.get(key),
+        serde_json::Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    })
+}
+
+/// Builds the dismissible error overlay shown on top of the page when compilation fails.
+fn error_overlay(errors: &[String]) -> String {
+    let messages: String = errors
+        .iter()
+        .map(|e| format!(
+            "<pre style=\"white-space:pre-wrap;margin:0 0 16px;color:#ffab91\">{}</pre>",
+            escape_html(e)
+        ))
+        .collect();
+    format!(
+        r#"<div id="breach-error-overlay" style="position:fixed;inset:0;z-index:2147483647;overflow:auto;padding:32px;background:rgba(24,24,24,.95);color:#fff;font:14px/1.5 ui-monospace,monospace">
+    <div style="margin-bottom:16px;font:bold 16px system-ui,sans-serif;color:#ff5722">B⧸REACH: {} compile error(s)</div>
+    {}
+    <button onclick="this.parentNode.remove()" style="padding:6px 12px;cursor:pointer">Dismiss</button>
+</div>"#,
+        errors.len(),
+        messages
+    )
+}
+
+/// Where a stylesheet being compiled comes from, for error messages.
+struct StylingOrigin<'a> {
+    /// The source file; `None` for stdin.
+    path: Option<&'a Path>,
+    /// The media query of a media stylesheet.
+    media: Option<&'a str>,
+    /// The name of a variant stylesheet.
+    variant: Option<&'a str>,
+}
+
+impl StylingOrigin<'_> {
+    /// Formats `error` from the section with `marker` as
+    /// `app.breach ¦scss media="print": <error>` (or `¦scss@print` for a variant).
+    fn describe(&self, marker: &str, error: &dyn std::fmt::Display) -> String {
+        let file = self.path.map_or_else(|| "<stdin>".to_string(), |p| p.display().to_string());
+        match (self.media, self.variant) {
+            (_, Some(variant)) => format!("{} ¦{}@{}: {}", file, marker, variant, error),
+            (Some(media), None) => format!("{} ¦{} media=\"{}\": {}", file, marker, media, error),
+            (None, None) => format!("{} ¦{}: {}", file, marker, error),
+        }
+    }
+}
+
+/// Processes styling content with markers and compiles the sections of one
+/// stylesheet as needed, returning the final CSS content. Compile errors are
+/// logged and appended to `errors`, prefixed with `origin` (the source file, plus
+/// the media query for a media stylesheet) and the failing section's marker.
+fn process_styling_content(
+    styling_content: &str,
+    options: &PrepareOptions,
+    strip_comments: bool,
+    origin: &StylingOrigin,
+    errors: &mut Vec<String>,
+) -> String {
     let mut final_css_sections = Vec::new();
+    let mut report = |marker: &str, error: &dyn std::fmt::Display| {
+        let message = origin.describe(marker, error);
+        tracing::error!("{}", message);
+        errors.push(message);
+    };
 
     // Split by /* EOF */ markers to get individual sections
     let sections = styling_content.split("/* EOF */");
@@ -330,7 +1701,17 @@ fn process_styling_content(styling_content: &str) -> String {
             continue;
         }
 
-        if let Some(css_content) = trimmed.strip_prefix("/* CSS */") {
+        if options.raw_styling {
+            // --no-scss: every block is served as written, markers aside
+            let raw = ["/* CSS */", "/* SCSS */", "/* STYLUS */"]
+                .iter()
+                .find_map(|marker| trimmed.strip_prefix(marker))
+                .unwrap_or(trimmed)
+                .trim();
+            if !raw.is_empty() {
+                final_css_sections.push(raw.to_string());
+            }
+        } else if let Some(css_content) = trimmed.strip_prefix("/* CSS */") {
             // CSS content - use as-is
             let css = css_content.trim();
             if !css.is_empty() {
@@ -340,13 +1721,33 @@ fn process_styling_content(styling_content: &str) -> String {
             // SCSS content - compile it
             let scss = scss_content.trim();
             if !scss.is_empty() {
-                match compile_scss_to_css(scss) {
+                match compile_scss_to_css(scss, strip_comments, &options.include_dirs) {
                     Ok(compiled_css) => {
                         final_css_sections.push(compiled_css);
                     }
                     Err(e) => {
-                        tracing::error!("Failed to compile SCSS, using original: {}", e);
-                        final_css_sections.push(scss.to_string());
+                        report("scss", &e);
+                        if options.style_fallback && !options.strict {
+                            tracing::warn!("Failed to compile SCSS, using original: {}", e);
+                            final_css_sections.push(scss.to_string());
+                        }
+                    }
+                }
+            }
+        } else if let Some(stylus_content) = trimmed.strip_prefix("/* STYLUS */") {
+            // Stylus content - compile it with the external compiler
+            let stylus = stylus_content.trim();
+            if !stylus.is_empty() {
+                match compile_stylus_to_css(stylus) {
+                    Ok(compiled_css) => {
+                        final_css_sections.push(compiled_css);
+                    }
+                    Err(e) => {
+                        report("stylus", &e);
+                        if options.style_fallback && !options.strict {
+                            tracing::warn!("Failed to compile Stylus, using original: {}", e);
+                            final_css_sections.push(stylus.to_string());
+                        }
                     }
                 }
             }
@@ -361,59 +1762,858 @@ fn process_styling_content(styling_content: &str) -> String {
     final_css_sections.join("\n\n")
 }
 
+/// Processes the script section for serving. Scripts are currently passed
+/// through unchanged; this is where transpilation plugs in.
+fn process_script_content(script_content: &str) -> String {
+    script_content.to_string()
+}
+
+/// The compiled stylesheets `previous` served for the variants of `parsed`, by
+/// name; `None` for a variant it didn't have.
+fn variant_styling(parsed: &ParsedContent, previous: &PreparedContent) -> Vec<Option<Bytes>> {
+    parsed
+        .variants
+        .iter()
+        .map(|v| previous.bodies.variants.iter().find(|p| p.name == v.name)?.styling.clone())
+        .collect()
+}
+
 /// Prepares the parsed content for serving by compiling SCSS to CSS and injecting links.
-/// Generates a fingerprint for cache busting.
-pub fn prepare(parsed: ParsedContent) -> PreparedContent {
-    let mut parsed = parsed;
-
-    // Process styling content with markers
-    let final_css = if let Some(styling_content) = &parsed.styling {
-        let processed_css = process_styling_content(styling_content);
-        if processed_css.trim().is_empty() {
-            None
-        } else {
-            Some(processed_css)
+/// Generates a fingerprint for cache busting. When `previous` is given, sections that
+/// fail to compile fall back to their previously compiled output, and content
+/// prepared from the same sources and options is reused without compiling.
+pub fn prepare(
+    parsed: ParsedContent,
+    options: &PrepareOptions,
+    previous: Option<&PreparedContent>,
+) -> PreparedContent {
+    let source_sizes = SectionSizes {
+        markup: parsed.markup.as_ref().map_or(0, String::len),
+        styling: parsed.styling.as_ref().map_or(0, String::len),
+        script: parsed.script.as_ref().map_or(0, String::len),
+    };
+
+    // Styling and script compilation are independent, so run them side by side;
+    // both must finish before fingerprinting
+    let strip_comments = options.strip_comments || parsed.config.strip_comments;
+    let styling_hash = styling_source_hash(&parsed, strip_comments, options);
+    // Unchanged content prepares to the same outputs, e.g. after a restart with
+    // `--cache` or a save without edits, so they are served as they are
+    let key = content_key(&parsed, styling_hash, options);
+    if let Some(previous) = previous.filter(|previous| previous.content_key == Some(key)) {
+        tracing::debug!("Content unchanged, reusing the prepared outputs");
+        return PreparedContent {
+            parsed,
+            prepared_at: SystemTime::now(),
+            timings: PrepareTimings::default(),
+            ..previous.clone()
+        };
+    }
+    // Unchanged styling sources compile to the same result, so the previous
+    // outputs and errors are reused instead of running the compilers again
+    let reused = previous.filter(|previous| previous.styling_source_hash == Some(styling_hash));
+    if reused.is_some() {
+        tracing::debug!("Styling unchanged, reusing the compiled CSS");
+    }
+
+    let ((final_css, final_media, final_variants, styling_errors, styling_time), (final_script, script_time)) = std::thread::scope(|scope| {
+        let styling_task = scope.spawn(|| {
+            let started = Instant::now();
+            if let Some(previous) = reused {
+                let bodies = &previous.bodies;
+                let variants = variant_styling(&parsed, previous);
+                return (bodies.styling.clone(), bodies.media.clone(), variants, previous.styling_errors.clone(), started.elapsed());
+            }
+            let mut errors = Vec::new();
+            let mut compile = |styling_content: &str, media: Option<&str>, variant: Option<&str>| {
+                let origin = StylingOrigin { path: parsed.source_path.as_deref(), media, variant };
+                let processed_css =
+                    process_styling_content(styling_content, options, strip_comments, &origin, &mut errors);
+                if processed_css.trim().is_empty() {
+                    None
+                } else {
+                    Some(Bytes::from(processed_css))
+                }
+            };
+            let css = parsed.styling.as_deref().and_then(|styling| compile(styling, None, None));
+            let media: Vec<ServedMedia> = parsed
+                .media_styling
+                .iter()
+                .filter_map(|m| {
+                    compile(&m.styling, Some(&m.media), None).map(|body| ServedMedia {
+                        media: m.media.clone(),
+                        slug: m.slug.clone(),
+                        body,
+                    })
+                })
+                .collect();
+            let variants: Vec<Option<Bytes>> =
+                parsed.variants.iter().map(|v| compile(&v.styling, None, Some(&v.name))).collect();
+            (css, media, variants, errors, started.elapsed())
+        });
+        let started = Instant::now();
+        let script = parsed.script.as_deref().map(|s| Bytes::from(process_script_content(s)));
+        let script = (script, started.elapsed());
+        let styling = styling_task
+            .join()
+            .unwrap_or_else(|payload| std::panic::resume_unwind(payload));
+        (styling, script)
+    });
+
+    // A broken styling section keeps serving the last good CSS so the rest of the
+    // page stays usable; the overlay still reports the failure
+    let styling_failed = !styling_errors.is_empty();
+    let (styling, media, variant_css) = match previous {
+        Some(previous) if styling_failed && reused.is_none() && !options.style_fallback && !options.strict => {
+            tracing::warn!("Styling failed to compile, keeping previously compiled CSS");
+            (previous.bodies.styling.clone(), previous.bodies.media.clone(), variant_styling(&parsed, previous))
         }
-    } else {
-        None
+        _ => (final_css, final_media, final_variants),
     };
+    let script = final_script;
+    let mut errors: Vec<String> = parsed.load_error.iter().cloned().chain(styling_errors.iter().cloned()).collect();
 
-    // Update parsed content with final CSS
-    parsed.styling = final_css.clone();
+    // Markup placeholders are filled from the data section, if there is one
+    let data = parsed.data.as_deref().and_then(|data| match serde_json::from_str::<serde_json::Value>(data) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            let error = format!("¦json: invalid JSON: {}", e);
+            tracing::error!("{}", error);
+            errors.push(error);
+            None
+        }
+    });
+    let markup: Option<Cow<str>> = match (&parsed.markup, &data) {
+        (Some(markup), Some(data)) => Some(Cow::Owned(interpolate(markup, data))),
+        (markup, _) => markup.as_deref().map(Cow::Borrowed),
+    };
 
     // Generate fingerprint including all content
+    let hashing_started = Instant::now();
     let mut hasher = FxHasher64::default();
     if let Some(m) = &parsed.markup {
         hasher.write(m.as_bytes());
     }
-    if let Some(s) = &parsed.styling {
-        hasher.write(s.as_bytes());
+    if let Some(h) = &parsed.head {
+        hasher.write(h.as_bytes());
+    }
+    if let Some(d) = &parsed.data {
+        hasher.write(d.as_bytes());
+    }
+    if let Some(s) = &styling {
+        hasher.write(s);
     }
-    if let Some(s) = &parsed.script {
-        hasher.write(s.as_bytes());
+    for m in &media {
+        hasher.write(m.media.as_bytes());
+        hasher.write(&m.body);
+    }
+    for (v, css) in parsed.variants.iter().zip(&variant_css) {
+        hasher.write(v.route.as_bytes());
+        hasher.write(css.as_deref().unwrap_or_default());
+    }
+    if let Some(s) = &script {
+        hasher.write(s);
     } else {
         // Include a marker when no script is present to differentiate fingerprints
         hasher.write(b"NO_SCRIPT");
     }
+    if let Some(f) = &parsed.favicon {
+        hasher.write(f);
+    }
+    // Errors are part of the fingerprint so a different failure still reloads the overlay
+    for e in &errors {
+        hasher.write(e.as_bytes());
+    }
     let fingerprint = hasher.finish();
+    let mut hashing = hashing_started.elapsed();
 
     // Generate HTML with injected links
-    let html_injected = parsed
-        .markup
-        .as_deref()
-        .map(|m| inject_links_once(m, parsed.styling.is_some(), parsed.script.is_some(), fingerprint));
+    let injection_started = Instant::now();
+    // Without any markup to show it on, a load error gets an empty page of its own
+    let markup = markup.as_deref().or(parsed.load_error.as_ref().map(|_| ""));
+    let page = markup.map(|m| {
+        let assets = PageAssets {
+            css_version: styling.as_deref().map(|s| section_fingerprint(Some(s))),
+            js_version: script.as_deref().map(|s| section_fingerprint(Some(s))),
+            media_styling: &media,
+            head: parsed.head.as_deref(),
+        };
+        let html = inject_links_once(
+            m,
+            assets,
+            &parsed.config,
+            fingerprint,
+            options,
+        );
+        // Browsers cache favicons hard; a versioned link makes an edited one show
+        match &parsed.favicon {
+            Some(favicon) if !has_icon_link(m) && !parsed.head.as_deref().is_some_and(has_icon_link) => {
+                let link = format!(r#"<link rel="icon" href="/favicon.ico?v={}">"#, body_version(favicon));
+                inject_head_tags(&html, &[link])
+            }
+            _ => html,
+        }
+    });
+    let with_overlay = |html: String| {
+        if errors.is_empty() {
+            html
+        } else {
+            inject_js_script(&html, &error_overlay(&errors))
+        }
+    };
+    // A variant page is the shared page with the variant's stylesheet linked last
+    let variants: Vec<ServedVariant> = parsed
+        .variants
+        .iter()
+        .zip(variant_css)
+        .map(|(variant, css)| {
+            let stem = format!("style@{}", variant.name);
+            let html = page.as_deref().map(|page| {
+                let html = match &css {
+                    Some(css) if !page.contains(&format!("/{}.css", stem)) => {
+                        let href = asset_url(&stem, "css", body_version(css), options.hashed_assets);
+                        inject_head_tags(page, &[format!(r#"<link rel="stylesheet" href="{}">"#, href)])
+                    }
+                    _ => page.to_string(),
+                };
+                Bytes::from(with_overlay(html))
+            });
+            ServedVariant { name: variant.name.clone(), route: variant.route.clone(), html, styling: css }
+        })
+        .collect();
+    let html = page.map(|page| Bytes::from(with_overlay(page)));
 
+    let injection = injection_started.elapsed();
+
+    // Media stylesheets count as styling when reporting what changed
+    let hashing_started = Instant::now();
+    let mut styling_hasher = FxHasher64::default();
+    styling_hasher.write_u64(section_fingerprint(styling.as_deref()));
+    for m in &media {
+        styling_hasher.write(m.media.as_bytes());
+        styling_hasher.write(&m.body);
+    }
+    for v in &variants {
+        styling_hasher.write(v.name.as_bytes());
+        styling_hasher.write(v.styling.as_deref().unwrap_or_default());
+    }
+    // The markup is the filled source with its `¦head`, not the injected page, which
+    // also carries the asset versions and so changes with any other section
+    let mut markup_hasher = FxHasher64::default();
+    markup_hasher.write(markup.unwrap_or_default().as_bytes());
+    markup_hasher.write(parsed.head.as_deref().unwrap_or_default().as_bytes());
+    let section_fingerprints = SectionFingerprints {
+        markup: markup_hasher.finish(),
+        styling: styling_hasher.finish(),
+        script: section_fingerprint(script.as_deref()),
+    };
+    hashing += hashing_started.elapsed();
+
+    let timings = PrepareTimings {
+        styling: styling_time,
+        script: script_time,
+        injection,
+        hashing,
+        ..Default::default()
+    };
+    tracing::debug!(
+        "Prepared content in styling={:?} script={:?} injection={:?} hashing={:?}",
+        timings.styling,
+        timings.script,
+        timings.injection,
+        timings.hashing
+    );
+
+    let favicon = parsed.favicon.clone();
     PreparedContent {
         fingerprint,
         parsed,
-        html_injected,
+        errors,
+        styling_errors,
+        source_sizes,
+        prepared_at: SystemTime::now(),
+        timings,
+        section_fingerprints,
+        bodies: ServedBodies {
+            html,
+            styling,
+            script,
+            media,
+            variants,
+            favicon,
+            hashed_names: options.hashed_assets,
+        },
+        styling_source_hash: Some(styling_hash),
+        content_key: Some(key),
+        minified: MinifiedBodies::default(),
+    }
+}
+
+/// Reads all of `reader`, failing once more than `max_size` bytes have been read.
+/// `path` names the source in the error.
+pub fn read_limited<R: Read>(reader: R, path: &Path, max_size: u64) -> Result<Vec<u8>, BreachError> {
+    let mut bytes = Vec::new();
+    reader.take(max_size.saturating_add(1)).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > max_size {
+        return Err(BreachError::FileTooLarge {
+            path: path.to_path_buf(),
+            size: bytes.len() as u64,
+            limit: max_size,
+        });
+    }
+    Ok(bytes)
+}
+
+/// Reads a source file, refusing files larger than `max_size` bytes before
+/// loading them into memory.
+pub fn read_source_file(path: &Path, max_size: u64) -> Result<Vec<u8>, BreachError> {
+    let file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    if size > max_size {
+        return Err(BreachError::FileTooLarge {
+            path: path.to_path_buf(),
+            size,
+            limit: max_size,
+        });
+    }
+    // The file may still grow between the check and the read
+    read_limited(file, path, max_size)
+}
+
+/// Decodes source bytes as UTF-8, except for sections whose marker declares an
+/// `encoding` directive (e.g. `¦html encoding=latin1`): their lines are decoded
+/// from that encoding, which may be any label browsers accept. Marker lines are
+/// always UTF-8. An unknown or non-ASCII-compatible encoding (UTF-16) is
+/// reported and the section stays UTF-8.
+///
+/// Every file read goes through here (the main file, includes, manifest
+/// sections), so each one also has its leading byte order mark dropped and
+/// its newlines normalized, and spliced files can't leave a stray U+FEFF or
+/// `\r` in the middle of the content.
+pub fn decode_source(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    // Plain UTF-8 without the directive, as almost every file is, needs no pass
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        if !s.contains("encoding") {
+            return normalize_newlines(s);
+        }
+    }
+    let mut decoded = String::with_capacity(bytes.len());
+    let mut encoding: Option<&'static Encoding> = None;
+    for line in bytes.split_inclusive(|b| *b == b'\n') {
+        let utf8 = String::from_utf8_lossy(line);
+        if marker_section(&utf8).is_some() {
+            encoding = marker_directive(&utf8, "encoding").and_then(|label| {
+                match Encoding::for_label(label.trim().as_bytes()) {
+                    Some(e) if e.is_ascii_compatible() => Some(e).filter(|e| *e != encoding_rs::UTF_8),
+                    _ => {
+                        tracing::warn!("Unsupported encoding {:?} in {:?}, reading the section as UTF-8", label, utf8.trim());
+                        None
+                    }
+                }
+            });
+            decoded.push_str(&utf8);
+            continue;
+        }
+        match encoding {
+            Some(e) => decoded.push_str(&e.decode_without_bom_handling(line).0),
+            None => decoded.push_str(&utf8),
+        }
     }
+    normalize_newlines(&decoded)
+}
+
+/// File name of the favicon picked up from next to the source file.
+pub const FAVICON_NAME: &str = "favicon.ico";
+
+/// Returns true if `html` links an icon of its own (`rel="icon"` or
+/// `rel="shortcut icon"`).
+fn has_icon_link(html: &str) -> bool {
+    ["rel=\"icon\"", "rel=\"shortcut icon\"", "rel='icon'", "rel=icon"]
+        .iter()
+        .any(|rel| find_case_insensitive(html, rel).is_some())
 }
 
 /// Loads and prepares content from a .breach file at the given path.
-pub fn load_prepared_from_file(path: &PathBuf) -> Result<PreparedContent, Box<dyn Error>> {
-    let bytes = fs::read(path)?;
-    let s = String::from_utf8_lossy(&bytes).to_string();
-    let parsed = parse_breach_content(&s);
-    Ok(prepare(parsed))
+/// `previous` is the content currently being served, if any (see [`prepare`]).
+pub fn load_prepared_from_file(
+    path: &PathBuf,
+    options: &PrepareOptions,
+    previous: Option<&PreparedContent>,
+) -> Result<PreparedContent, BreachError> {
+    let started = Instant::now();
+    let mut dependencies = Vec::new();
+    let expanded = if manifest::is_manifest(path) {
+        manifest::assemble(path, options.max_file_size, &options.include_dirs, &mut dependencies)?
+    } else {
+        let bytes = read_source_file(path, options.max_file_size)?;
+        let s = decode_source(&bytes);
+        expand_includes(&s, Some(path), options.max_file_size, &options.include_dirs, &mut dependencies)?
+    };
+    let read = started.elapsed();
+    let started = Instant::now();
+    let mut parsed = parse_breach_content(&expanded);
+    let parse = started.elapsed();
+    // A favicon next to the source is served and watched like an include
+    let favicon_path = path.with_file_name(FAVICON_NAME);
+    if favicon_path.is_file() {
+        match read_source_file(&favicon_path, options.max_file_size) {
+            Ok(bytes) => {
+                parsed.favicon = Some(Bytes::from(bytes));
+                dependencies.push(favicon_path.canonicalize().unwrap_or(favicon_path));
+            }
+            Err(e) => tracing::warn!("Failed to read {:?}: {}", favicon_path, e),
+        }
+    }
+    parsed.source_path = Some(path.clone());
+    parsed.dependencies = dependencies;
+    let mut prepared = prepare(parsed, options, previous);
+    prepared.timings.read = read;
+    prepared.timings.parse = parse;
+    Ok(prepared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> PrepareOptions {
+        PrepareOptions { livereload: false, ..Default::default() }
+    }
+
+    #[test]
+    fn json_errors_are_not_carried_over_by_reused_styling() {
+        let source = |markup: &str| format!("¦html\n{}\n¦css\np {{ color: red }}\n¦json\n{{ broken\n", markup);
+        let first = prepare(parse_breach_content(&source("<p>one</p>")), &options(), None);
+        assert_eq!(first.errors.len(), 1);
+        let second = prepare(parse_breach_content(&source("<p>two</p>")), &options(), Some(&first));
+        assert_eq!(second.errors.len(), 1);
+        let third = prepare(parse_breach_content(&source("<p>three</p>")), &options(), Some(&second));
+        assert_eq!(third.errors.len(), 1);
+        assert!(third.styling_errors.is_empty());
+    }
+
+    #[test]
+    fn fixed_json_clears_its_error_with_unchanged_styling() {
+        let broken = "¦html\n<p>{{ a }}</p>\n¦css\np { color: red }\n¦json\n{ broken\n";
+        let fixed = "¦html\n<p>{{ a }}</p>\n¦css\np { color: red }\n¦json\n{ \"a\": 1 }\n";
+        let first = prepare(parse_breach_content(broken), &options(), None);
+        assert_eq!(first.errors.len(), 1);
+        let second = prepare(parse_breach_content(fixed), &options(), Some(&first));
+        assert_eq!(second.styling_source_hash, first.styling_source_hash);
+        assert!(second.errors.is_empty());
+        assert!(String::from_utf8_lossy(second.bodies.html.as_deref().unwrap()).contains("<p>1</p>"));
+    }
+
+    fn styling(prepared: &PreparedContent) -> String {
+        String::from_utf8_lossy(prepared.bodies.styling.as_deref().unwrap_or_default()).into_owned()
+    }
+
+    #[test]
+    fn stylus_marker_opens_a_stylus_section() {
+        assert_eq!(marker_section("¦stylus"), Some(SectionType::StylusStyling));
+        assert_eq!(marker_section("¦Stylus  "), Some(SectionType::StylusStyling));
+        assert_eq!(marker_section("¦styl"), None);
+    }
+
+    #[test]
+    fn stylus_blocks_follow_css_and_scss() {
+        let parsed = parse_breach_content("¦stylus\nbody\n  color red\n¦scss\n$a: 1px;\n¦css\np { top: 0 }\n");
+        let styling = parsed.styling.unwrap();
+        let css = styling.find("/* CSS */").unwrap();
+        let scss = styling.find("/* SCSS */").unwrap();
+        let stylus = styling.find("/* STYLUS */\nbody\n  color red\n/* EOF */").unwrap();
+        assert!(css < scss && scss < stylus);
+    }
+
+    #[test]
+    fn raw_styling_serves_stylus_as_written() {
+        let options = PrepareOptions { raw_styling: true, ..options() };
+        let prepared = prepare(parse_breach_content("¦stylus\nbody\n  color red\n"), &options, None);
+        assert_eq!(styling(&prepared), "body\n  color red");
+        assert!(prepared.errors.is_empty());
+    }
+
+    #[test]
+    fn marker_names_are_ascii_and_end_at_whitespace() {
+        for line in ["¦css2", "¦css-foo", "¦css×", "¦cssé", "¦css.min"] {
+            assert!(!starts_with_section_marker(line, "css"), "{}", line);
+            assert_eq!(marker_section(line), None, "{}", line);
+        }
+        for line in ["¦css", "¦CSS", "  ¦css", "\u{feff}¦css", "¦css\tmedia=print", "¦css media=print"] {
+            assert!(starts_with_section_marker(line, "css"), "{:?}", line);
+        }
+        assert!(!starts_with_section_marker("css", "css"));
+        assert!(!starts_with_section_marker("a ¦css", "css"));
+    }
+
+    #[test]
+    fn markers_after_nbsp_and_zero_width_characters_are_found() {
+        for line in ["\u{a0}¦css", "\u{200b}¦css", "\u{200d}¦css", "\u{2060}¦css", "\u{feff}\u{a0} ¦css"] {
+            assert!(starts_with_section_marker(line, "css"), "{:?}", line);
+            assert_eq!(marker_section(line), Some(SectionType::CssStyling), "{:?}", line);
+        }
+        let parsed = parse_breach_content("\u{a0}¦html\r<p>a</p>\r\n\u{200b}¦css\np { top: 0 }\r");
+        assert_eq!(parsed.markup.as_deref(), Some("<p>a</p>"));
+        assert_eq!(parsed.styling.as_deref(), Some("/* CSS */\np { top: 0 }\n/* EOF */"));
+    }
+
+    #[test]
+    fn lines_that_are_not_markers_stay_in_their_section() {
+        let parsed = parse_breach_content("¦html\n<p>a</p>\n¦css2\n¦css×\n");
+        assert_eq!(parsed.markup.as_deref(), Some("<p>a</p>\n¦css2\n¦css×"));
+        assert_eq!(parsed.styling, None);
+    }
+
+    fn inject(html: &str, options: &PrepareOptions) -> String {
+        let assets = PageAssets { css_version: Some(1), js_version: Some(2), ..Default::default() };
+        inject_links_once(html, assets, &BreachConfig::default(), 3, options)
+    }
+
+    #[test]
+    fn unlinked_assets_are_injected() {
+        let page = inject("<html><head></head><body><p>hi</p></body></html>", &options());
+        assert_eq!(page.matches(r#"<link rel="stylesheet" href="/style.css?v=1">"#).count(), 1);
+        assert_eq!(page.matches(r#"<script src="/script.js?v=2"></script>"#).count(), 1);
+        assert!(page.find("/style.css").unwrap() < page.find("</head>").unwrap());
+        assert!(page.find("/script.js").unwrap() < page.find("</body>").unwrap());
+    }
+
+    #[test]
+    fn assets_the_markup_links_are_not_injected_again() {
+        let html = r#"<head><link rel="stylesheet" href="/style.css"></head><body><script src="/script.js"></script></body>"#;
+        let page = inject(html, &options());
+        assert_eq!(page.matches("/style.css").count(), 1);
+        assert_eq!(page.matches("/script.js").count(), 1);
+    }
+
+    #[test]
+    fn livereload_is_injected_even_when_the_assets_are_linked() {
+        let html = r#"<head><link rel="stylesheet" href="/style.css"></head><body><script src="/script.js"></script></body>"#;
+        let page = inject(html, &PrepareOptions::default());
+        assert!(page.contains("WebSocket") && !page.contains("__BREACH_"));
+        assert_eq!(page.matches("/script.js").count(), 1);
+    }
+
+    #[test]
+    fn markup_blocks_concatenate_in_authoring_order() {
+        let parsed = parse_breach_content("¦html\n<header></header>\n¦css\np { top: 0 }\n¦html\n<main></main>  \n\n¦js\nlet a;\n¦html\n<footer></footer>\n");
+        assert_eq!(parsed.markup.as_deref(), Some("<header></header>\n<main></main>\n<footer></footer>"));
+        assert_eq!(parsed.script.as_deref(), Some("let a;"));
+    }
+
+    #[test]
+    fn empty_markup_blocks_leave_no_markup() {
+        let parsed = parse_breach_content("¦html\n\n¦css\np { top: 0 }\n¦html\n   \n");
+        assert_eq!(parsed.markup, None);
+    }
+
+    fn page_with_title(markup: &str, title: Option<&str>) -> String {
+        let config = BreachConfig { title: title.map(str::to_string), ..Default::default() };
+        inject_links_once(markup, PageAssets::default(), &config, 0, &options())
+    }
+
+    #[test]
+    fn markup_title_wins_over_the_configured_one() {
+        let page = page_with_title("<html><head><title>Own</title></head><body></body></html>", Some("Config"));
+        assert_eq!(page.matches("<title>").count(), 1);
+        assert!(page.contains("<title>Own</title>") && !page.contains("Config"));
+    }
+
+    #[test]
+    fn configured_title_fills_in_a_missing_one() {
+        let page = page_with_title("<html><head></head><body></body></html>", Some("A & B"));
+        assert!(page.contains("<title>A &amp; B</title>"));
+        assert!(page.find("<title>").unwrap() < page.find("</head>").unwrap());
+        let page = page_with_title("<p>bare</p>", Some("Bare"));
+        assert!(page.contains("<head>") && page.contains("<title>Bare</title>"));
+    }
+
+    #[test]
+    fn no_title_is_made_up() {
+        let page = page_with_title("<html><head></head><body></body></html>", None);
+        assert!(!page.contains("<title>"));
+    }
+
+    #[test]
+    fn invisible_whitespace_keeps_the_fingerprint() {
+        let fingerprint = |source: &str| prepare(parse_breach_content(source), &options(), None).fingerprint;
+        let base = fingerprint("¦html\n<p>a</p>\n¦css\np { top: 0 }\n¦js\nlet a;");
+        assert_eq!(fingerprint("¦html\n<p>a</p>\n¦css\np { top: 0 }\n¦js\nlet a;\n"), base);
+        assert_eq!(fingerprint("¦html\n<p>a</p>  \n\n¦css\np { top: 0 }\t\n¦js\nlet a;\n\n\n"), base);
+        assert_eq!(fingerprint("¦html\r\n<p>a</p>\r\n¦css\r\np { top: 0 }\r\n¦js\r\nlet a;\r\n"), base);
+        assert_ne!(fingerprint("¦html\n<p>a</p>\n¦css\np { top: 0 }\n¦js\nlet  a;"), base);
+    }
+
+    #[test]
+    fn leading_indentation_is_kept() {
+        let parsed = parse_breach_content("¦html\n<ul>\n  <li>a</li>   \n</ul>\n\n");
+        assert_eq!(parsed.markup.as_deref(), Some("<ul>\n  <li>a</li>\n</ul>"));
+    }
+
+    #[test]
+    fn config_section_overrides_front_matter() {
+        let parsed = parse_breach_content("+++\ntitle = Front\nstrip_comments = true\n+++\n¦config\ntitle = Section\n¦html\n<p></p>\n");
+        assert_eq!(parsed.config.title.as_deref(), Some("Section"));
+        assert!(parsed.config.strip_comments);
+        assert_eq!(parsed.config.lines.get("strip_comments"), Some(&3));
+        assert_eq!(parsed.config.lines.get("title"), Some(&6));
+        assert_eq!(parsed.markup.as_deref(), Some("<p></p>"));
+    }
+
+    #[test]
+    fn compiled_scss_keeps_comments_unless_stripping() {
+        let scss = "/*! license */\n/* note */\np { color: red; }\n";
+        let kept = compile_scss_to_css(scss, false, &[]).unwrap();
+        assert!(kept.contains("/*! license */") && kept.contains("/* note */"));
+        let stripped = compile_scss_to_css(scss, true, &[]).unwrap();
+        assert!(stripped.contains("/*! license */"));
+        assert!(!stripped.contains("/* note */"));
+    }
+
+    #[test]
+    fn stripping_comments_leaves_strings_alone() {
+        let css = "a::after { content: \"/* not a comment */\"; }\n/* gone */\nb { top: 0 }";
+        assert_eq!(strip_css_comments(css), "a::after { content: \"/* not a comment */\"; }\nb { top: 0 }");
+    }
+
+    // The `?v=` of every asset the page links
+    fn asset_versions(prepared: &PreparedContent) -> Vec<String> {
+        let html = String::from_utf8_lossy(prepared.bodies.html.as_deref().unwrap()).into_owned();
+        html.split("?v=").skip(1).map(|rest| rest.split('"').next().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn livereload_client_changes_leave_asset_versions_alone() {
+        let source = "¦html\n<p>a</p>\n¦css\np { top: 0 }\n¦js\nlet a;\n";
+        let auto = prepare(parse_breach_content(source), &PrepareOptions::default(), None);
+        let changed_client = PrepareOptions {
+            reload_mode: ReloadMode::Manual,
+            reconnect_attempts: 3,
+            reload_message: "refresh".to_string(),
+            ..Default::default()
+        };
+        let manual = prepare(parse_breach_content(source), &changed_client, None);
+        assert_ne!(auto.bodies.html, manual.bodies.html);
+        assert_eq!(asset_versions(&auto).len(), 2);
+        assert_eq!(asset_versions(&auto), asset_versions(&manual));
+        let without = prepare(parse_breach_content(source), &options(), None);
+        assert_eq!(asset_versions(&auto), asset_versions(&without));
+    }
+
+    #[test]
+    fn asset_versions_follow_their_own_section() {
+        let versions = |css: &str| {
+            let source = format!("¦html\n<p>a</p>\n¦css\n{}\n¦js\nlet a;\n", css);
+            asset_versions(&prepare(parse_breach_content(&source), &options(), None))
+        };
+        let (red, blue) = (versions("p { color: red }"), versions("p { color: blue }"));
+        assert_ne!(red[0], blue[0]);
+        assert_eq!(red[1], blue[1]);
+    }
+
+    #[test]
+    fn trailing_colon_on_a_marker_is_optional() {
+        for (with, without) in [("¦css:", "¦css"), ("¦js:", "¦js"), ("¦JavaScript:", "¦javascript"), ("¦html: ", "¦html")] {
+            assert_eq!(marker_section(with), marker_section(without), "{}", with);
+            assert!(marker_section(with).is_some(), "{}", with);
+        }
+        assert_eq!(marker_directive("¦css: media=print", "media"), Some("print"));
+        assert_eq!(marker_variant("¦css@dark:"), Some("dark".to_string()));
+        assert_eq!(marker_section("¦css::"), None);
+        assert_eq!(marker_section("¦css:print"), None);
+    }
+
+    #[test]
+    fn colon_markers_parse_like_plain_ones() {
+        let plain = parse_breach_content("¦html\n<p>a</p>\n¦css\np { top: 0 }\n¦js\nlet a;\n");
+        let colon = parse_breach_content("¦html:\n<p>a</p>\n¦css:\np { top: 0 }\n¦js:\nlet a;\n");
+        assert_eq!(plain, colon);
+    }
+
+    fn inject_head(html: &str, head: &str) -> String {
+        let assets = PageAssets { css_version: Some(1), head: Some(head), ..Default::default() };
+        inject_links_once(html, assets, &BreachConfig::default(), 3, &options())
+    }
+
+    #[test]
+    fn head_content_comes_before_the_stylesheet() {
+        let page = inject_head("<html><head></head><body></body></html>", r#"<meta name="theme-color" content="red">"#);
+        let head = page.find("theme-color").unwrap();
+        assert!(head < page.find("/style.css").unwrap(), "{}", page);
+        assert!(page.find("/style.css").unwrap() < page.find("</head>").unwrap());
+    }
+
+    #[test]
+    fn head_title_and_charset_give_way_to_the_markup() {
+        let head = r#"<meta charset="latin1"><title>Head</title>"#;
+        let page = inject_head(r#"<html><head><meta charset="utf-8"><title>Page</title></head></html>"#, head);
+        assert_eq!(page.matches("<title>").count(), 1);
+        assert!(page.contains("<title>Page</title>"), "{}", page);
+        assert_eq!(page.matches("<meta charset").count(), 1);
+        assert!(!page.contains("latin1"), "{}", page);
+        let page = inject_head("<html><head></head></html>", head);
+        assert!(page.contains("<title>Head</title>") && page.contains("latin1"), "{}", page);
+    }
+
+    #[test]
+    fn head_tags_create_a_head_when_the_markup_has_none() {
+        let tags = ["<title>T</title>".to_string()];
+        assert_eq!(inject_head_tags("<p>a</p>", &tags), "<head>\n    <meta charset=\"utf-8\">\n    <title>T</title>\n</head>\n<p>a</p>");
+        assert_eq!(inject_head_tags("<html><p>a</p></html>", &tags), "<html>\n<head>\n    <title>T</title>\n</head><p>a</p></html>");
+        let charset = [r#"<meta charset="latin1">"#.to_string()];
+        assert_eq!(inject_head_tags("<p>a</p>", &charset), "<head>\n    <meta charset=\"latin1\">\n</head>\n<p>a</p>");
+        let page = inject_head("<p>a</p>", "<title>Head</title>");
+        assert!(page.find("<title>Head</title>").unwrap() < page.find("<p>a</p>").unwrap(), "{}", page);
+    }
+
+    #[test]
+    fn multi_megabyte_single_line_sections_parse_whole() {
+        let script = "var a=1;".repeat(512 * 1024);
+        let source = format!("¦html\n<p>a</p>\n¦js\n{}\n¦css\np{{top:0}}\n", script);
+        let parsed = parse_breach_content(&source);
+        assert_eq!(parsed.script.as_deref(), Some(script.as_str()));
+        assert_eq!(parsed.styling.as_deref(), Some("/* CSS */\np{top:0}\n/* EOF */"));
+    }
+
+    #[test]
+    fn latin1_section_decodes_to_utf8() {
+        let mut source = b"\xef\xbb\xbf\xc2\xa6html encoding=latin1\r\n<p>caf\xe9 \xa9</p>\r\n".to_vec();
+        source.extend_from_slice("¦css\np::after { content: \"é\" }\n".as_bytes());
+        let decoded = decode_source(&source);
+        assert_eq!(decoded, "¦html encoding=latin1\n<p>café ©</p>\n¦css\np::after { content: \"é\" }\n");
+        let parsed = parse_breach_content(&decoded);
+        assert_eq!(parsed.markup.as_deref(), Some("<p>café ©</p>"));
+    }
+
+    #[test]
+    fn unsupported_encodings_leave_the_section_utf8() {
+        for label in ["utf-16", "klingon"] {
+            let source = format!("¦html encoding={}\n<p>é</p>\n", label);
+            assert_eq!(decode_source(source.as_bytes()), source);
+        }
+        // Invalid UTF-8 without a directive is replaced rather than rejected
+        assert_eq!(decode_source(b"\xc2\xa6html\n<p>\xe9</p>\n"), "¦html\n<p>\u{fffd}</p>\n");
+    }
+
+    #[test]
+    fn end_marker_closes_every_section_kind() {
+        let cases = [
+            ("html", "<p>a</p>"),
+            ("head", "<meta name=\"a\">"),
+            ("css", "p { top: 0 }"),
+            ("scss", "$a: 1px;"),
+            ("js", "let a;"),
+            ("json", "{ \"a\": 1 }"),
+            ("config", "title = A"),
+        ];
+        for (marker, body) in cases {
+            let closed = parse_breach_content(&format!("¦{}\n{}\n¦end\nnotes that belong nowhere\n", marker, body));
+            let plain = parse_breach_content(&format!("¦{}\n{}\n", marker, body));
+            assert_eq!(closed, plain, "¦{}", marker);
+        }
+    }
+
+    #[test]
+    fn sections_after_end_are_parsed_again() {
+        let parsed = parse_breach_content("¦html\n<p>a</p>\n¦END\ndropped\n¦end:\nalso dropped\n¦js\nlet a;\n¦end\n");
+        assert_eq!(parsed.markup.as_deref(), Some("<p>a</p>"));
+        assert_eq!(parsed.script.as_deref(), Some("let a;"));
+        assert_eq!(marker_section("¦end"), Some(SectionType::None));
+        assert_eq!(marker_section("¦ending"), None);
+    }
+
+    #[test]
+    fn end_marker_closes_a_scoped_block() {
+        let parsed = parse_breach_content("¦html\n<p>a</p>\n¦css scoped\np { top: 0 }\n¦end\nq { top: 1 }\n");
+        let styling = parsed.styling.unwrap();
+        assert!(styling.contains("top: 0") && !styling.contains("top: 1"));
+    }
+
+    #[test]
+    fn compiler_panics_become_errors() {
+        assert_eq!(catch_compiler_panic(|| 1), Ok(1));
+        let static_message = catch_compiler_panic(|| -> () { panic!("boom") });
+        assert_eq!(static_message, Err(ScssError::Panic("boom".to_string())));
+        let formatted = catch_compiler_panic(|| -> () { panic!("at {}", 3) });
+        assert_eq!(formatted, Err(ScssError::Panic("at 3".to_string())));
+        let other = catch_compiler_panic(|| std::panic::panic_any(7));
+        assert_eq!(other, Err(ScssError::Panic("unknown panic".to_string())));
+    }
+
+    #[test]
+    fn broken_scss_is_reported_not_fatal() {
+        let prepared = prepare(parse_breach_content("¦html\n<p>a</p>\n¦scss\np { color: $missing; }\n"), &options(), None);
+        assert_eq!(prepared.errors.len(), 1);
+        assert!(prepared.errors[0].contains("¦scss"), "{}", prepared.errors[0]);
+        assert!(prepared.bodies.html.is_some());
+    }
+
+    #[test]
+    fn styling_and_script_edits_leave_the_markup_fingerprint_alone() {
+        let prepared = |markup: &str, css: &str, js: &str| {
+            let source = format!("¦html\n{}\n¦css\n{}\n¦js\n{}\n", markup, css, js);
+            prepare(parse_breach_content(&source), &options(), None).section_fingerprints
+        };
+        let first = prepared("<p>hi</p>", "p { color: red }", "let a = 1;");
+        let second = prepared("<p>hi</p>", "p { color: blue }", "let a = 2;");
+        assert_eq!(first.markup, second.markup);
+        assert_ne!(first.styling, second.styling);
+        assert_ne!(first.script, second.script);
+        let third = prepared("<p>ho</p>", "p { color: blue }", "let a = 2;");
+        assert_ne!(second.markup, third.markup);
+    }
+
+    #[test]
+    fn two_scoped_blocks_get_their_own_attributes() {
+        let parsed = parse_breach_content(
+            "¦html\n<p>a</p>\n¦css scoped\np { top: 0 }\n¦html\n<p>b</p>\n¦css scoped\np { top: 1 }\n",
+        );
+        let (first, second) = (scope::attribute(0), scope::attribute(1));
+        assert_ne!(first, second);
+        let markup = parsed.markup.unwrap();
+        assert!(markup.contains(&format!("<p {}>a</p>", first)), "{}", markup);
+        assert!(markup.contains(&format!("<p {}>b</p>", second)), "{}", markup);
+        let styling = parsed.styling.unwrap();
+        assert!(styling.contains(&format!("p[{}] {{ top: 0 }}", first)), "{}", styling);
+        assert!(styling.contains(&format!("p[{}] {{ top: 1 }}", second)), "{}", styling);
+    }
+
+    #[test]
+    fn bom_prefixed_partials_splice_cleanly() {
+        let dir = std::env::temp_dir().join(format!("breach-include-bom-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("partial.html"), b"\xef\xbb\xbf<nav>menu</nav>\r\n<hr>").unwrap();
+        let source = "¦html\n¦include partial.html\n<p>a</p>";
+        std::fs::write(dir.join("page.breach"), source).unwrap();
+        let mut dependencies = Vec::new();
+        let expanded = expand_includes(source, Some(&dir.join("page.breach")), u64::MAX, &[], &mut dependencies);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let expanded = expanded.unwrap();
+        assert_eq!(expanded, "¦html\n<nav>menu</nav>\n<hr>\n<p>a</p>");
+        assert_eq!(dependencies.len(), 1);
+        let parsed = parse_breach_content(&expanded);
+        assert_eq!(parsed.markup.as_deref(), Some("<nav>menu</nav>\n<hr>\n<p>a</p>"));
+    }
+
+    #[test]
+    fn section_spans_cover_each_section() {
+        let source = "---\ntitle = a\n---\n¦html\n<p>a</p>\n¦css\np { top: 0 }\n¦js\nlet a;\n";
+        let (parsed, spans) = parse_breach_content_with_spans(source);
+        assert_eq!(parsed.markup.as_deref(), Some("<p>a</p>"));
+        let kinds: Vec<SectionType> = spans.iter().map(|span| span.kind).collect();
+        assert_eq!(kinds, [SectionType::Config, SectionType::Markup, SectionType::CssStyling, SectionType::Script]);
+        let text: Vec<&str> = spans.iter().map(|span| &source[span.range.clone()]).collect();
+        assert_eq!(text, ["---\ntitle = a\n---\n", "¦html\n<p>a</p>\n", "¦css\np { top: 0 }\n", "¦js\nlet a;\n"]);
+    }
+
+    #[test]
+    fn section_spans_are_offsets_into_the_raw_file() {
+        let source = "\u{feff}¦html\r\n<p>a</p>\r\n¦end\r\nignored\r\n¦js\r\nlet a;";
+        let spans = section_spans(source);
+        let text: Vec<&str> = spans.iter().map(|span| &source[span.range.clone()]).collect();
+        assert_eq!(text, ["¦html\r\n<p>a</p>\r\n", "¦js\r\nlet a;"]);
+        assert_eq!(spans[0].range.start, '\u{feff}'.len_utf8());
+        assert!(section_spans("no markers here").is_empty());
+    }
 }
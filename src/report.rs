@@ -0,0 +1,233 @@
+use crate::parser::{PrepareTimings, PreparedContent};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tracing::info;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether log lines may contain ANSI colors; set once logging is set up.
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Whether reload summaries are followed by a diff of the changed sections.
+static DIFF: AtomicBool = AtomicBool::new(false);
+
+/// Most removed and added lines shown per section by `--reload-diff`.
+const DIFF_LINES: usize = 20;
+
+/// Phase timings collected by `--profile`; `None` when profiling is off.
+static PROFILE: Mutex<Option<Profile>> = Mutex::new(None);
+
+/// Min, max and total duration of one phase over the warm reloads.
+#[derive(Clone, Copy, Default)]
+struct PhaseStats {
+    min: Duration,
+    max: Duration,
+    sum: Duration,
+}
+
+/// The timings of the first (cold) preparation and of every reload after it.
+#[derive(Default)]
+struct Profile {
+    cold: Option<PrepareTimings>,
+    reloads: u32,
+    warm: [PhaseStats; PHASES.len()],
+}
+
+/// A phase reported by `--profile`: its name and how to read its duration.
+type Phase = (&'static str, fn(&PrepareTimings) -> Duration);
+
+/// The phases reported by `--profile`, in pipeline order.
+const PHASES: [Phase; 7] = [
+    ("read", |t| t.read),
+    ("parse", |t| t.parse),
+    ("styling", |t| t.styling),
+    ("script", |t| t.script),
+    ("injection", |t| t.injection),
+    ("hashing", |t| t.hashing),
+    ("total", PrepareTimings::total),
+];
+
+/// Returns true if logs written to a stream should be colored: only when it is a
+/// terminal and `NO_COLOR` (https://no-color.org) is unset or empty.
+pub fn color_wanted(is_terminal: bool) -> bool {
+    is_terminal && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+/// Enables or disables colors in the reload summaries.
+pub fn set_color(enabled: bool) {
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Enables or disables the `--reload-diff` section diffs.
+pub fn set_diff(enabled: bool) {
+    DIFF.store(enabled, Ordering::Relaxed);
+}
+
+/// Enables `--profile`, collecting the timings of every preparation from now on.
+pub fn set_profile(enabled: bool) {
+    *PROFILE.lock().unwrap() = enabled.then(Profile::default);
+}
+
+/// Records the phase timings of a preparation for `--profile`. The first one
+/// is the cold start; the others are aggregated as warm reloads.
+pub fn profile(timings: &PrepareTimings) {
+    let mut profile = PROFILE.lock().unwrap();
+    let Some(profile) = profile.as_mut() else {
+        return;
+    };
+    if profile.cold.is_none() {
+        profile.cold = Some(*timings);
+        return;
+    }
+    for ((_, phase), stats) in PHASES.iter().zip(&mut profile.warm) {
+        let d = phase(timings);
+        stats.min = if profile.reloads == 0 { d } else { stats.min.min(d) };
+        stats.max = stats.max.max(d);
+        stats.sum += d;
+    }
+    profile.reloads += 1;
+}
+
+/// Logs the `--profile` table: each phase's cold time and its min, average and
+/// max over the warm reloads, in milliseconds. Does nothing when profiling is off.
+pub fn print_profile() {
+    let profile = PROFILE.lock().unwrap();
+    let Some(profile) = profile.as_ref() else {
+        return;
+    };
+    let ms = |d: Duration| format!("{:.2}", d.as_secs_f64() * 1000.0);
+    let mut table = format!("{:<10} {:>9} {:>9} {:>9} {:>9}", "phase", "cold", "min", "avg", "max");
+    for ((name, phase), stats) in PHASES.iter().zip(&profile.warm) {
+        let cold = profile.cold.as_ref().map_or("-".to_string(), |t| ms(phase(t)));
+        let warm = if profile.reloads == 0 {
+            ["-".to_string(), "-".to_string(), "-".to_string()]
+        } else {
+            [ms(stats.min), ms(stats.sum / profile.reloads), ms(stats.max)]
+        };
+        table.push_str(&format!("\n{:<10} {:>9} {:>9} {:>9} {:>9}", name, cold, warm[0], warm[1], warm[2]));
+    }
+    info!("Profile over {} reload(s), in ms (--profile):\n{}", profile.reloads, table);
+}
+
+/// Wraps `text` in an ANSI color when colored logs are enabled.
+fn paint(text: &str, color: &str) -> String {
+    if COLOR.load(Ordering::Relaxed) {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Names of the sections whose served output differs between two preparations.
+fn changed_sections(previous: &PreparedContent, current: &PreparedContent) -> Vec<&'static str> {
+    let (old, new) = (previous.section_fingerprints, current.section_fingerprints);
+    [
+        ("markup", old.markup != new.markup),
+        ("styling", old.styling != new.styling),
+        ("script", old.script != new.script),
+    ]
+    .into_iter()
+    .filter_map(|(name, changed)| changed.then_some(name))
+    .collect()
+}
+
+/// Logs the one-line summary of a reload: which sections changed, how long
+/// compiling took and whether anything failed. Details stay at debug level.
+pub fn reloaded(previous: &PreparedContent, current: &PreparedContent) {
+    let sections = changed_sections(previous, current);
+    let changed = if sections.is_empty() {
+        // Only the errors differ, e.g. the same failure moved to another line
+        "errors".to_string()
+    } else {
+        sections.join(", ")
+    };
+    let status = match current.errors.len() {
+        0 => paint("ok", GREEN),
+        n => paint(&format!("{} error(s)", n), RED),
+    };
+    info!(
+        "Reloaded {} {} {}",
+        changed,
+        paint(&format!("({:.1} ms)", current.timings.total().as_secs_f64() * 1000.0), DIM),
+        status
+    );
+    if DIFF.load(Ordering::Relaxed) {
+        for section in sections {
+            section_diff(section, previous, current);
+        }
+    }
+}
+
+/// Logs, for `--reload-diff`, that a change on disk left the served content as
+/// it was, so pages were not reloaded.
+pub fn unchanged() {
+    if DIFF.load(Ordering::Relaxed) {
+        info!("Not reloading: the change gives the same output as before");
+    }
+}
+
+/// The sources written in the file that make up the output `section`.
+fn section_sources<'a>(content: &'a PreparedContent, section: &str) -> Vec<(&'static str, &'a str)> {
+    let parsed = &content.parsed;
+    let sources: &[(&'static str, &'a Option<String>)] = match section {
+        "markup" => &[("markup", &parsed.markup), ("head", &parsed.head), ("data", &parsed.data)],
+        "styling" => &[("styling", &parsed.styling)],
+        _ => &[("script", &parsed.script)],
+    };
+    sources
+        .iter()
+        .map(|(name, text)| (*name, text.as_deref().unwrap_or_default()))
+        .collect()
+}
+
+/// Logs a diff of each source of the changed output `section`, or why there is
+/// none when only the output differs (e.g. an `@use`d file or a setting changed).
+fn section_diff(section: &str, previous: &PreparedContent, current: &PreparedContent) {
+    let mut diffed = false;
+    for ((name, old), (_, new)) in section_sources(previous, section).into_iter().zip(section_sources(current, section)) {
+        if old != new {
+            info!("Changed {}:\n{}", name, line_diff(old, new));
+            diffed = true;
+        }
+    }
+    if !diffed {
+        info!("Changed {} output, with the same source in the file", section);
+    }
+}
+
+/// A short diff between `old` and `new`: the lines between their common leading
+/// and trailing lines, removed ones prefixed with `-` and added ones with `+`,
+/// under a unified-diff style `@@` header. Each side shows at most
+/// [`DIFF_LINES`] lines, so one big edit doesn't flood the log.
+fn line_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let removed = &old[prefix..old.len() - suffix];
+    let added = &new[prefix..new.len() - suffix];
+    let mut diff = format!("@@ -{},{} +{},{} @@", prefix + 1, removed.len(), prefix + 1, added.len());
+    for (sign, lines, color) in [('-', removed, RED), ('+', added, GREEN)] {
+        for line in lines.iter().take(DIFF_LINES) {
+            diff.push('\n');
+            diff.push_str(&paint(&format!("{}{}", sign, line), color));
+        }
+        if lines.len() > DIFF_LINES {
+            diff.push_str(&format!("\n{} ... {} more line(s)", sign, lines.len() - DIFF_LINES));
+        }
+    }
+    diff
+}
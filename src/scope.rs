@@ -0,0 +1,229 @@
+use fxhash::FxHasher64;
+use std::hash::Hasher;
+
+/// At-rules whose block holds regular style rules, which are scoped as well.
+/// Other at-rules (`@keyframes`, `@font-face`, ...) are copied unchanged.
+const GROUPING_AT_RULES: &[&str] = &["media", "supports", "container", "layer", "document"];
+
+/// Elements whose content is raw text, never markup.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+/// Legacy pseudo-elements that may be written with a single colon.
+const LEGACY_PSEUDO_ELEMENTS: &[&str] = &["before", "after", "first-line", "first-letter"];
+
+/// The attribute tying scoped styles to markup block number `block`, e.g.
+/// `data-breach-3f2a1c`. Derived from the block's position so it stays the same
+/// across reloads. The block number goes through a single odd multiply, which
+/// maps distinct numbers to distinct low bits, so no two blocks share an
+/// attribute.
+pub fn attribute(block: usize) -> String {
+    let mut hasher = FxHasher64::default();
+    hasher.write(b"breach-scope");
+    hasher.write_usize(block);
+    format!("data-breach-{:06x}", hasher.finish() & 0xff_ffff)
+}
+
+/// Returns the byte offset of the first `stop` byte at nesting depth 0 in `s`,
+/// skipping strings, comments and bracketed parts.
+fn find_top_level(s: &str, stop: &[u8]) -> Option<usize> {
+    let bytes = s.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' | b'\'' => {
+                let quote = bytes[i];
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = s[i + 2..].find("*/").map_or(bytes.len(), |end| i + 2 + end + 1);
+            }
+            b'(' | b'[' => depth += 1,
+            b')' | b']' => depth = depth.saturating_sub(1),
+            b if depth == 0 && stop.contains(&b) => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Returns the byte offset just past the `}` closing a block whose `{` precedes
+/// `s`, or the length of `s` if it is unterminated.
+fn block_end(s: &str) -> usize {
+    let mut depth = 1usize;
+    let mut rest = 0;
+    while let Some(offset) = find_top_level(&s[rest..], b"{}") {
+        let at = rest + offset;
+        if s.as_bytes()[at] == b'{' {
+            depth += 1;
+        } else {
+            depth -= 1;
+            if depth == 0 {
+                return at + 1;
+            }
+        }
+        rest = at + 1;
+    }
+    s.len()
+}
+
+/// Adds `[attr]` to a single complex selector, on its last compound selector and
+/// before any pseudo-element, so `.card p::before` becomes `.card p[attr]::before`.
+fn scope_selector(selector: &str, attr: &str) -> String {
+    let bytes = selector.as_bytes();
+    let mut last_compound = 0;
+    let mut depth = 0usize;
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'(' | b'[' => depth += 1,
+            b')' | b']' => depth = depth.saturating_sub(1),
+            b' ' | b'\t' | b'\n' | b'>' | b'+' | b'~' if depth == 0 => last_compound = i + 1,
+            _ => {}
+        }
+    }
+    let compound = &selector[last_compound..];
+    let mut insert_at = selector.len();
+    let mut depth = 0usize;
+    for (i, b) in compound.bytes().enumerate() {
+        match b {
+            b'(' | b'[' => depth += 1,
+            b')' | b']' => depth = depth.saturating_sub(1),
+            b':' if depth == 0 => {
+                let name = compound[i..].trim_start_matches(':');
+                let legacy = LEGACY_PSEUDO_ELEMENTS
+                    .iter()
+                    .any(|p| name.get(..p.len()).is_some_and(|n| n.eq_ignore_ascii_case(p)));
+                if compound[i..].starts_with("::") || legacy {
+                    insert_at = last_compound + i;
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    format!("{}[{}]{}", &selector[..insert_at], attr, &selector[insert_at..])
+}
+
+/// Scopes every selector in a comma-separated selector list.
+fn scope_selector_list(prelude: &str, attr: &str) -> String {
+    let mut scoped = Vec::new();
+    let mut rest = prelude;
+    loop {
+        let end = find_top_level(rest, b",").unwrap_or(rest.len());
+        let selector = rest[..end].trim();
+        if !selector.is_empty() {
+            scoped.push(scope_selector(selector, attr));
+        }
+        if end == rest.len() {
+            break;
+        }
+        rest = &rest[end + 1..];
+    }
+    scoped.join(", ")
+}
+
+/// Rewrites the selectors of every style rule in `css` to only match elements
+/// carrying `attr`, recursing into grouping at-rules like `@media`.
+pub fn scope_css(css: &str, attr: &str) -> String {
+    let mut out = String::with_capacity(css.len() + css.len() / 4);
+    let mut rest = css;
+    while let Some(offset) = find_top_level(rest, b"{;") {
+        let prelude = &rest[..offset];
+        let body_start = offset + 1;
+        if rest.as_bytes()[offset] == b';' {
+            out.push_str(&rest[..body_start]);
+            rest = &rest[body_start..];
+            continue;
+        }
+        let end = body_start + block_end(&rest[body_start..]);
+        let body = &rest[body_start..end];
+        // Comments in front of the rule are kept where they are, outside the selector
+        let mut trimmed = prelude.trim();
+        out.push_str(&prelude[..prelude.len() - prelude.trim_start().len()]);
+        while let Some(comment) = trimmed.strip_prefix("/*") {
+            let end = comment.find("*/").map_or(trimmed.len(), |end| end + 4);
+            out.push_str(&trimmed[..end]);
+            out.push('\n');
+            trimmed = trimmed[end..].trim_start();
+        }
+        if let Some(at_rule) = trimmed.strip_prefix('@') {
+            let name = at_rule.split(|c: char| !c.is_ascii_alphanumeric() && c != '-').next().unwrap_or_default();
+            if GROUPING_AT_RULES.iter().any(|r| r.eq_ignore_ascii_case(name)) {
+                out.push_str(trimmed);
+                out.push_str(" {");
+                let inner = body.strip_suffix('}').unwrap_or(body);
+                out.push_str(&scope_css(inner, attr));
+                if body.ends_with('}') {
+                    out.push('}');
+                }
+            } else {
+                out.push_str(trimmed);
+                out.push_str(" {");
+                out.push_str(body);
+            }
+        } else {
+            out.push_str(&scope_selector_list(trimmed, attr));
+            out.push_str(" {");
+            out.push_str(body);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Adds `attr` to every element's start tag in `html`, leaving comments,
+/// doctypes, end tags and the contents of raw-text elements like `<script>`
+/// untouched.
+pub fn scope_html(html: &str, attr: &str) -> String {
+    let mut out = String::with_capacity(html.len() + html.len() / 8);
+    let mut rest = html;
+    while let Some(lt) = rest.find('<') {
+        out.push_str(&rest[..lt]);
+        rest = &rest[lt..];
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map_or(rest.len(), |end| end + 3);
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        let name_len = rest[1..]
+            .bytes()
+            .take_while(|b| b.is_ascii_alphanumeric() || *b == b'-' || *b == b':')
+            .count();
+        if name_len == 0 || !rest.as_bytes()[1].is_ascii_alphabetic() {
+            out.push('<');
+            rest = &rest[1..];
+            continue;
+        }
+        let name = rest[1..=name_len].to_ascii_lowercase();
+        out.push_str(&rest[..=name_len]);
+        out.push(' ');
+        out.push_str(attr);
+        rest = &rest[name_len + 1..];
+        if RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+            let close = format!("</{}", name);
+            let end = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_never_share_an_attribute() {
+        let attributes: std::collections::HashSet<String> = (0..4096).map(attribute).collect();
+        assert_eq!(attributes.len(), 4096);
+        assert_eq!(attribute(2), attribute(2));
+    }
+}
@@ -1,117 +1,915 @@
-use crate::parser;
+use crate::{assets, parser, watch};
 use arc_swap::ArcSwap;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ntex::http::header::{self, HeaderValue};
+use ntex::http::{HeaderMap, Method};
+use ntex::util::Bytes;
 use ntex::web::{self, HttpResponse, Error};
 use ntex::ws;
+use ntex::{Middleware, Service, ServiceCtx};
+use std::net::{IpAddr, SocketAddr};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::broadcast;
 
 #[derive(Clone)]
 pub struct AppState {
     pub content: Arc<ArcSwap<parser::PreparedContent>>,
     pub reload_tx: broadcast::Sender<()>,
+    /// The .breach file being served, or `None` when it was read from stdin.
+    pub breach_path: Option<PathBuf>,
+    /// Options used whenever the content is re-prepared.
+    pub options: parser::PrepareOptions,
+    /// Whether the development endpoints under `/__breach/` are enabled.
+    pub dev: bool,
+    /// Number of currently connected livereload WebSocket clients.
+    pub ws_clients: Arc<AtomicUsize>,
+    /// Shell command run after each content change, before pages reload.
+    pub on_reload: Option<String>,
+    /// Serve the page for unknown paths so client-side routes can be deep-linked.
+    pub spa: bool,
+    /// Match static file paths case-insensitively when there is no exact match.
+    pub ignore_case: bool,
+    /// Whether `/` and `/index.html` both serve the page or one redirects to the other.
+    pub index_redirect: IndexRedirect,
+    /// Reload count and last error, reported by the health endpoint.
+    pub reload_stats: watch::ReloadStats,
+    /// When the last request was handled, for `--timeout`.
+    pub activity: Arc<Activity>,
 }
 
-/// Helper function to serve content with consistent response handling
+/// Time of the last handled request, shared by every worker.
+pub struct Activity {
+    started: Instant,
+    /// Milliseconds from `started` to the last request.
+    last: AtomicU64,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Activity { started: Instant::now(), last: AtomicU64::new(0) }
+    }
+}
+
+impl Activity {
+    /// Records activity now.
+    pub fn touch(&self) {
+        self.last.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// How long ago the last activity was.
+    pub fn idle_for(&self) -> Duration {
+        self.started.elapsed().saturating_sub(Duration::from_millis(self.last.load(Ordering::Relaxed)))
+    }
+}
+
+/// How the two URLs of the page, `/` and `/index.html`, relate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexRedirect {
+    /// Serve the page at both.
+    #[default]
+    None,
+    /// Redirect `/index.html` to `/`, the canonical URL.
+    Root,
+    /// Redirect `/` to `/index.html`, e.g. to mirror a host that needs the file name.
+    Index,
+}
+
+impl FromStr for IndexRedirect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(IndexRedirect::None),
+            "root" => Ok(IndexRedirect::Root),
+            "index" => Ok(IndexRedirect::Index),
+            other => Err(format!("unknown index redirect '{}' (expected 'none', 'root' or 'index')", other)),
+        }
+    }
+}
+
+/// Permanent redirect to `location`, keeping the request's query string.
+pub(crate) fn redirect(req: &web::HttpRequest, location: &str) -> HttpResponse {
+    let location = match req.query_string() {
+        "" => location.to_string(),
+        query => format!("{}?{}", location, query),
+    };
+    HttpResponse::MovedPermanently()
+        .header("Location", location.as_str())
+        .header("Cache-Control", "no-cache")
+        .finish()
+}
+
+impl AppState {
+    /// The directory the .breach file lives in (the working directory for stdin),
+    /// which sibling assets are served from.
+    pub fn content_root(&self) -> &Path {
+        self.breach_path
+            .as_deref()
+            .and_then(Path::parent)
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+    }
+}
+
+/// Decrements the connected client count when a WebSocket connection ends.
+struct ClientGuard(Arc<AtomicUsize>);
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns true if the peer connected over a loopback interface.
+fn is_loopback(addr: Option<SocketAddr>) -> bool {
+    match addr.map(|a| a.ip()) {
+        Some(IpAddr::V4(ip)) => ip.is_loopback(),
+        Some(IpAddr::V6(ip)) => ip
+            .to_ipv4_mapped()
+            .map(|v4| v4.is_loopback())
+            .unwrap_or_else(|| ip.is_loopback()),
+        None => false,
+    }
+}
+
+/// Routes answered without `--auth`, so health checks and container probes
+/// need no credentials.
+const UNAUTHENTICATED_ROUTES: &[&str] = &["/__breach/health", "/__breach/ready", "/__breach/live"];
+
+/// Middleware enforcing `--auth` HTTP basic auth on every route but the health
+/// and probe endpoints, the livereload WebSocket included. Passes everything
+/// through when no credentials are configured.
+#[derive(Clone)]
+pub struct BasicAuth {
+    /// The full expected `Authorization` value (`Basic <base64>`).
+    expected: Option<Rc<str>>,
+    skip_localhost: bool,
+}
+
+impl BasicAuth {
+    pub fn new(credentials: Option<&(String, String)>, skip_localhost: bool) -> Self {
+        let expected = credentials.map(|(user, pass)| {
+            let encoded = BASE64.encode(format!("{}:{}", user, pass));
+            Rc::from(format!("Basic {}", encoded))
+        });
+        BasicAuth { expected, skip_localhost }
+    }
+
+    /// Returns true if the request may pass: auth is off, the peer is a skipped
+    /// local one, or the `Authorization` header carries the right credentials.
+    fn allows(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> bool {
+        let Some(expected) = self.expected.as_deref() else {
+            return true;
+        };
+        if self.skip_localhost && is_loopback(peer) {
+            return true;
+        }
+        let Some(provided) = headers.get("Authorization").map(|v| v.as_bytes()) else {
+            return false;
+        };
+        // The scheme is case-insensitive; the credentials are compared without
+        // stopping at the first mismatching byte.
+        let (scheme, credentials) = expected.split_at("Basic ".len());
+        provided.len() == expected.len()
+            && provided[..scheme.len()].eq_ignore_ascii_case(scheme.as_bytes())
+            && provided[scheme.len()..]
+                .iter()
+                .zip(credentials.as_bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+impl<S> Middleware<S> for BasicAuth {
+    type Service = BasicAuthService<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        BasicAuthService { auth: self.clone(), service }
+    }
+}
+
+pub struct BasicAuthService<S> {
+    auth: BasicAuth,
+    service: S,
+}
+
+impl<S, Err> Service<web::WebRequest<Err>> for BasicAuthService<S>
+where
+    S: Service<web::WebRequest<Err>, Response = web::WebResponse, Error = Error>,
+    Err: web::ErrorRenderer,
+{
+    type Response = web::WebResponse;
+    type Error = Error;
+
+    ntex::forward_ready!(service);
+
+    async fn call(
+        &self,
+        req: web::WebRequest<Err>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        if UNAUTHENTICATED_ROUTES.contains(&req.path()) || self.auth.allows(req.headers(), req.peer_addr()) {
+            return ctx.call(&self.service, req).await;
+        }
+        tracing::debug!("Rejected unauthenticated request for {} from {:?}", req.path(), req.peer_addr());
+        let response = HttpResponse::Unauthorized()
+            .header("WWW-Authenticate", "Basic realm=\"b-reach\", charset=\"UTF-8\"")
+            .finish();
+        Ok(req.into_response(response))
+    }
+}
+
+/// Middleware recording every request in [`Activity`], at its start and end so a
+/// slow response doesn't count as idle time.
+#[derive(Clone)]
+pub struct TrackActivity(pub Arc<Activity>);
+
+impl<S> Middleware<S> for TrackActivity {
+    type Service = TrackActivityService<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        TrackActivityService { activity: self.0.clone(), service }
+    }
+}
+
+pub struct TrackActivityService<S> {
+    activity: Arc<Activity>,
+    service: S,
+}
+
+impl<S, Err> Service<web::WebRequest<Err>> for TrackActivityService<S>
+where
+    S: Service<web::WebRequest<Err>, Response = web::WebResponse, Error = Error>,
+    Err: web::ErrorRenderer,
+{
+    type Response = web::WebResponse;
+    type Error = Error;
+
+    ntex::forward_ready!(service);
+
+    async fn call(
+        &self,
+        req: web::WebRequest<Err>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        self.activity.touch();
+        let response = ctx.call(&self.service, req).await;
+        self.activity.touch();
+        response
+    }
+}
+
+/// Stops `server` gracefully once it has gone `timeout` without requests and
+/// without connected livereload clients (`--timeout`). An open page keeps the
+/// server alive; the idle time counts from when the last one disconnected.
+pub async fn stop_when_idle(server: ntex::server::Server, state: AppState, timeout: Duration) {
+    loop {
+        ntex::time::sleep(ntex::time::Seconds(1)).await;
+        if state.ws_clients.load(Ordering::Relaxed) > 0 {
+            state.activity.touch();
+        } else if state.activity.idle_for() >= timeout {
+            tracing::info!(
+                "No requests or connected pages for {}s, shutting down (--timeout)",
+                timeout.as_secs()
+            );
+            server.stop(true).await;
+            return;
+        }
+    }
+}
+
+/// Security headers added to every response unless `--no-security-headers` is given.
+pub const SECURITY_HEADERS: &[(&str, &str)] = &[("X-Content-Type-Options", "nosniff")];
+
+/// Content coding of served bodies. Bodies are currently always sent as-is; the
+/// coding is part of the ETag so a compressed variant can never share a validator
+/// with the identity body in an intermediary cache.
+const CONTENT_ENCODING: &str = "identity";
+
+/// Builds the entity tag for a body with the given fingerprint and content coding.
+fn etag_for(fingerprint: u64, encoding: &str) -> String {
+    format!("\"{:x}-{}\"", fingerprint, encoding)
+}
+
+/// Returns true if the request's `If-None-Match` already names `etag`.
+fn etag_matches(req: &web::HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get("If-None-Match")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        }))
+        .unwrap_or(false)
+}
+
+/// Returns true for a `?min=1` request, asking for the minified output.
+fn wants_minified(req: &web::HttpRequest) -> bool {
+    req.query_string().split('&').any(|pair| pair == "min=1")
+}
+
+/// Helper function to serve content with consistent response handling. Under
+/// `--dev`, a `?min=1` request gets the body from `minified` instead, when given.
 fn serve_content<F>(
+    req: &web::HttpRequest,
     data: &web::types::State<AppState>,
     content_getter: F,
+    minified: Option<fn(&parser::PreparedContent) -> Option<Bytes>>,
     content_type: &str,
     path: &str,
 ) -> HttpResponse
 where
-    F: Fn(&parser::PreparedContent) -> Option<&String>,
+    F: Fn(&parser::PreparedContent) -> Option<Bytes>,
 {
     let prepared = data.content.load();
-    match content_getter(&prepared) {
+    let minified = minified.filter(|_| data.dev && wants_minified(req));
+    let content = match minified {
+        Some(minified) => minified(&prepared),
+        None => content_getter(&prepared),
+    };
+    match content {
         Some(content) => {
-            tracing::info!("Serving content for path: {}, MIME: {}; charset=utf-8. Content length: {}", path, content_type, content.len());
+            let mut etag = etag_for(prepared.fingerprint, CONTENT_ENCODING);
+            // The minified body is another representation and needs its own validator
+            if minified.is_some() {
+                etag.insert_str(etag.len() - 1, "-min");
+            }
+            if etag_matches(req, &etag) {
+                tracing::debug!("Content for path {} not modified (ETag {})", path, etag);
+                return HttpResponse::NotModified()
+                    .header("ETag", etag.as_str())
+                    .header("Vary", "Accept-Encoding")
+                    .header("Cache-Control", "no-cache")
+                    .finish();
+            }
+            let content_type = assets::utf8_content_type(content_type);
+            tracing::debug!("Serving content for path: {}, MIME: {}. Content length: {}", path, content_type, content.len());
+            // The shared buffer is sent as-is; ntex sets Content-Length from its size
             HttpResponse::Ok()
-                .content_type(&format!("{}; charset=utf-8", content_type))
+                .content_type(&content_type)
                 .header("Cache-Control", "no-cache")
-                .header("X-Content-Type-Options", "nosniff")
+                .header("ETag", etag.as_str())
+                .header("Vary", "Accept-Encoding")
                 .header("Accept-Ranges", "bytes")
-                .body(content.to_string())
+                .body(content)
         }
         None => {
-            tracing::warn!("Resource not found for path: {}, MIME: {}; charset=utf-8. Data was None.", path, content_type);
+            tracing::warn!("Resource not found for path: {}, MIME: {}. Data was None.", path, content_type);
             HttpResponse::NotFound()
                 .content_type("text/plain")
                 .header("Cache-Control", "no-cache")
-                .header("X-Content-Type-Options", "nosniff")
                 .body("Resource not found")
         }
     }
 }
 
-pub async fn index(data: web::types::State<AppState>) -> HttpResponse {
-    serve_content(&data, |p| p.html_injected.as_ref(), "text/html", "/")
+pub async fn index(req: web::HttpRequest, data: web::types::State<AppState>) -> HttpResponse {
+    if data.index_redirect == IndexRedirect::Index && req.path() == "/" {
+        return redirect(&req, "/index.html");
+    }
+    serve_content(&req, &data, |p| p.bodies.html.clone(), None, "text/html", "/")
 }
 
-pub async fn index_html(data: web::types::State<AppState>) -> HttpResponse {
-    index(data).await
+pub async fn index_html(req: web::HttpRequest, data: web::types::State<AppState>) -> HttpResponse {
+    if data.index_redirect == IndexRedirect::Root {
+        return redirect(&req, "/");
+    }
+    serve_content(&req, &data, |p| p.bodies.html.clone(), None, "text/html", "/index.html")
 }
 
-pub async fn style_css(data: web::types::State<AppState>) -> HttpResponse {
+pub async fn style_css(req: web::HttpRequest, data: web::types::State<AppState>) -> HttpResponse {
     let prepared = data.content.load();
-    tracing::info!("Request for /style.css. Styling content present: {}", prepared.parsed.styling.is_some());
-    serve_content(&data, |p| p.parsed.styling.as_ref(), "text/css", "/style.css")
+    tracing::debug!("Request for /style.css. Styling content present: {}", prepared.parsed.styling.is_some());
+    let minified = parser::PreparedContent::minified_styling;
+    serve_content(&req, &data, |p| p.bodies.styling.clone(), Some(minified), "text/css", "/style.css")
 }
 
-pub async fn script_js(data: web::types::State<AppState>) -> HttpResponse {
-    serve_content(&data, |p| p.parsed.script.as_ref(), "application/javascript", "/script.js")
+/// Serves a media-specific stylesheet (`/style.<slug>.css`) if one exists for
+/// the request path.
+fn media_style_css(req: &web::HttpRequest, data: &web::types::State<AppState>) -> Option<HttpResponse> {
+    let slug = req.path().strip_prefix("/style.")?.strip_suffix(".css")?;
+    if !data.content.load().bodies.media.iter().any(|m| m.slug == slug) {
+        return None;
+    }
+    Some(serve_content(
+        req,
+        data,
+        |p| p.bodies.media.iter().find(|m| m.slug == slug).map(|m| m.body.clone()),
+        None,
+        "text/css",
+        req.path(),
+    ))
 }
 
-pub async fn favicon_ico() -> HttpResponse {
-    HttpResponse::NoContent()
-        .header("Cache-Control", "public, max-age=31536000, immutable")
-        .header("X-Content-Type-Options", "nosniff")
-        .header("Accept-Ranges", "bytes")
-        .finish()
+/// Serves the page of a styling variant at its route, or its stylesheet at
+/// `/style@<name>.css`, if the request path is one of them.
+fn variant(req: &web::HttpRequest, data: &web::types::State<AppState>) -> Option<HttpResponse> {
+    let path = req.path();
+    let prepared = data.content.load();
+    if let Some(name) = path.strip_prefix("/style@").and_then(|rest| rest.strip_suffix(".css")) {
+        prepared.bodies.variants.iter().find(|v| v.name == name)?;
+        let getter = |p: &parser::PreparedContent| p.bodies.variants.iter().find(|v| v.name == name)?.styling.clone();
+        return Some(serve_content(req, data, getter, None, "text/css", path));
+    }
+    let route = path.strip_suffix('/').filter(|route| !route.is_empty()).unwrap_or(path);
+    prepared.bodies.variants.iter().find(|v| v.route == route)?;
+    let getter = |p: &parser::PreparedContent| p.bodies.variants.iter().find(|v| v.route == route)?.html.clone();
+    Some(serve_content(req, data, getter, None, "text/html", path))
+}
+
+/// Serves `/style.<hash>.css`, `/style.<slug>.<hash>.css`,
+/// `/style@<name>.<hash>.css` and `/script.<hash>.js`. The current body is served whatever the hash; only a
+/// hash matching it is marked immutable, so an outdated URL requested around an
+/// edit is never cached as the new content.
+fn hashed_asset(req: &web::HttpRequest, data: &web::types::State<AppState>) -> Option<HttpResponse> {
+    let (rest, ext) = req.path().strip_prefix('/')?.rsplit_once('.')?;
+    let (stem, hash) = rest.rsplit_once('.')?;
+    if hash.len() != 16 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let version = u64::from_str_radix(hash, 16).ok()?;
+    let getter = |p: &parser::PreparedContent| match (stem, ext) {
+        ("style", "css") => p.bodies.styling.clone(),
+        ("script", "js") => p.bodies.script.clone(),
+        (stem, "css") if stem.starts_with("style@") => {
+            let name = &stem["style@".len()..];
+            p.bodies.variants.iter().find(|v| v.name == name)?.styling.clone()
+        }
+        (stem, "css") => {
+            let slug = stem.strip_prefix("style.")?;
+            p.bodies.media.iter().find(|m| m.slug == slug).map(|m| m.body.clone())
+        }
+        _ => None,
+    };
+    let current = getter(&data.content.load())?;
+    let content_type = if ext == "js" { "application/javascript" } else { "text/css" };
+    let mut response = serve_content(req, data, getter, None, content_type, req.path());
+    if parser::body_version(&current) == version {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=31536000, immutable"),
+        );
+    }
+    Some(response)
+}
+
+pub async fn script_js(req: web::HttpRequest, data: web::types::State<AppState>) -> HttpResponse {
+    let minified = parser::PreparedContent::minified_script;
+    serve_content(&req, &data, |p| p.bodies.script.clone(), Some(minified), "application/javascript", "/script.js")
+}
+
+/// Serves the `favicon.ico` next to the .breach file, revalidated like the other
+/// outputs so an edited icon is fetched again. Without one the 404 is not cached,
+/// so an icon added while the server runs shows up.
+pub async fn favicon_ico(req: web::HttpRequest, data: web::types::State<AppState>) -> HttpResponse {
+    if data.content.load().bodies.favicon.is_some() {
+        return serve_content(&req, &data, |p| p.bodies.favicon.clone(), None, "image/x-icon", "/favicon.ico");
+    }
+    HttpResponse::NotFound().header("Cache-Control", "no-cache").finish()
+}
+
+/// Response for a request that failed on the server side (as opposed to asking
+/// for something missing, which is a 404). The error is logged; with `--dev`
+/// the page shows it, otherwise only a generic message is sent.
+fn internal_error(data: &AppState, what: &str, error: &dyn std::fmt::Display) -> HttpResponse {
+    tracing::error!("{}: {}", what, error);
+    let mut response = HttpResponse::InternalServerError();
+    response.header("Cache-Control", "no-cache");
+    if !data.dev {
+        return response.content_type("text/plain").body("Internal server error");
+    }
+    response.content_type("text/html; charset=utf-8").body(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>500 Internal Server Error</title>
+<style>
+body {{ font-family: system-ui, sans-serif; background: #1e1e1e; color: #eee; margin: 2rem; }}
+h1 {{ color: #ff6b6b; font-size: 1.25rem; }}
+pre {{ background: #2a2a2a; padding: 1rem; border-left: 4px solid #ff6b6b; white-space: pre-wrap; }}
+</style>
+</head>
+<body>
+<h1>500 Internal Server Error</h1>
+<p>{}</p>
+<pre>{}</pre>
+</body>
+</html>"#,
+        parser::escape_html(what),
+        parser::escape_html(&error.to_string())
+    ))
 }
 
 pub async fn not_found() -> HttpResponse {
     HttpResponse::NotFound()
         .content_type("text/plain")
         .header("Cache-Control", "no-cache")
-        .header("X-Content-Type-Options", "nosniff")
         .body("Page not found")
 }
 
+/// Fixed routes serving the content, redirected to from any other casing.
+const CORE_ROUTES: &[&str] = &["/index.html", "/style.css", "/script.js", "/favicon.ico"];
+
+/// Handles requests no route matched: media stylesheets and files next to the
+/// .breach file are served as static assets. With `--spa`, other GET requests for paths that don't look
+/// like files (no extension in the last segment) get the page itself, so
+/// client-side routes like `/about` can be opened directly. Other casings of
+/// the fixed routes redirect to them, and with `--ignore-case` static files
+/// match regardless of case.
+pub async fn fallback(req: web::HttpRequest, data: web::types::State<AppState>) -> HttpResponse {
+    if req.path().starts_with("/__breach") {
+        return not_found().await;
+    }
+    let is_read = req.method() == Method::GET || req.method() == Method::HEAD;
+    if is_read {
+        if let Some(response) = hashed_asset(&req, &data) {
+            return response;
+        }
+        if let Some(response) = media_style_css(&req, &data) {
+            return response;
+        }
+        if let Some(response) = variant(&req, &data) {
+            return response;
+        }
+        match assets::serve(&req, data.content_root(), false) {
+            Ok(Some(response)) => return response,
+            Ok(None) => {}
+            Err(e) => return internal_error(&data, &format!("Failed to read {}", req.path()), &e),
+        }
+        // `/Style.css` and the like are the compiled outputs, whatever the
+        // case-insensitive filesystem the page was written on suggests
+        if let Some(route) = CORE_ROUTES.iter().find(|route| route.eq_ignore_ascii_case(req.path())) {
+            return redirect(&req, route);
+        }
+        if data.ignore_case {
+            match assets::serve(&req, data.content_root(), true) {
+                Ok(Some(response)) => return response,
+                Ok(None) => {}
+                Err(e) => return internal_error(&data, &format!("Failed to read {}", req.path()), &e),
+            }
+        }
+    }
+    if !data.spa {
+        return not_found().await;
+    }
+    if !is_read {
+        return HttpResponse::MethodNotAllowed()
+            .content_type("text/plain")
+            .header("Allow", "GET, HEAD")
+            .header("Cache-Control", "no-cache")
+            .body("Method not allowed");
+    }
+    let last_segment = req.path().rsplit('/').next().unwrap_or_default();
+    if last_segment.contains('.') {
+        return not_found().await;
+    }
+    tracing::debug!("Serving page for client-side route {}", req.path());
+    index(req, data).await
+}
+
+/// Re-reads the .breach file immediately and broadcasts a reload, bypassing the
+/// watcher debounce. Meant for editor integrations that already know when they
+/// saved; requires `--dev` and a loopback client.
+pub async fn trigger_reload(
+    req: web::HttpRequest,
+    data: web::types::State<AppState>,
+) -> HttpResponse {
+    if !data.dev {
+        return not_found().await;
+    }
+    if !is_loopback(req.peer_addr()) {
+        tracing::warn!("Rejected reload request from non-local peer {:?}", req.peer_addr());
+        return HttpResponse::Forbidden()
+            .content_type("text/plain")
+            .header("Cache-Control", "no-cache")
+            .body("Reload endpoint is only available from localhost");
+    }
+
+    let Some(breach_path) = &data.breach_path else {
+        return HttpResponse::Conflict()
+            .content_type("text/plain")
+            .header("Cache-Control", "no-cache")
+            .body("Content was read from stdin or a URL; there is no file to reload");
+    };
+
+    match watch::reload(
+        &data.content,
+        breach_path,
+        &data.options,
+        data.on_reload.as_deref(),
+        &data.reload_tx,
+        &data.reload_stats,
+    ) {
+        Ok(fingerprint) => HttpResponse::Ok()
+            .content_type("application/json")
+            .header("Cache-Control", "no-cache")
+            .body(format!(r#"{{"fingerprint":"{}"}}"#, fingerprint)),
+        Err(e) => internal_error(&data, "Reload failed", &e),
+    }
+}
+
+/// Health check reporting the served fingerprint and how long the last
+/// `prepare()` phases took, in milliseconds. Always available, unlike the other
+/// `/__breach/` endpoints, so it can back container or uptime probes.
+pub async fn health(data: web::types::State<AppState>) -> HttpResponse {
+    let prepared = data.content.load();
+    let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+    let last_error = match data.reload_stats.last_error() {
+        Some((at, message)) => format!(
+            r#"{{"at_ms":{},"message":{}}}"#,
+            at.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis(),
+            parser::json_string(&message)
+        ),
+        None => "null".to_string(),
+    };
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .header("Cache-Control", "no-cache")
+        .body(format!(
+            r#"{{"status":"ok","fingerprint":"{}","errors":{},"reloads":{},"last_error":{},"last_reload_ms":{{"styling":{:.3},"script":{:.3},"injection":{:.3}}}}}"#,
+            prepared.fingerprint,
+            prepared.errors.len(),
+            data.reload_stats.reloads(),
+            last_error,
+            ms(prepared.timings.styling),
+            ms(prepared.timings.script),
+            ms(prepared.timings.injection)
+        ))
+}
+
+/// Readiness probe: 503 until content compiled without errors has been
+/// served, 200 from then on.
+pub async fn ready(data: web::types::State<AppState>) -> HttpResponse {
+    let (mut response, status) = if data.reload_stats.is_ready() {
+        (HttpResponse::Ok(), "ready")
+    } else {
+        (HttpResponse::ServiceUnavailable(), "starting")
+    };
+    response
+        .content_type("application/json")
+        .header("Cache-Control", "no-cache")
+        .body(format!(r#"{{"status":"{}"}}"#, status))
+}
+
+/// Liveness probe: 200 for as long as the server answers requests.
+pub async fn live() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .header("Cache-Control", "no-cache")
+        .body(r#"{"status":"ok"}"#)
+}
+
+/// Plain-text listing of the directory the .breach file lives in, with exact file
+/// names and sizes, to track down missing assets and filename case typos.
+/// Requires `--dev`; the .breach source itself is left out.
+pub async fn list_files(data: web::types::State<AppState>) -> HttpResponse {
+    if !data.dev {
+        return not_found().await;
+    }
+
+    let root = data.content_root();
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) => return internal_error(&data, &format!("Failed to list {}", root.display()), &e),
+    };
+
+    let mut lines: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension().is_none_or(|e| e != "breach"))
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            match entry.metadata() {
+                Ok(meta) if meta.is_dir() => format!("{}/", name),
+                Ok(meta) => format!("{}\t{}", name, meta.len()),
+                Err(_) => name,
+            }
+        })
+        .collect();
+    lines.sort();
+
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .header("Cache-Control", "no-cache")
+        .body(lines.join("\n"))
+}
+
+/// Development dashboard summarizing the served sections, their sizes before and
+/// after compilation, the fingerprint and connected clients. Refreshes itself via
+/// the livereload WebSocket. Requires `--dev`.
+pub async fn dashboard(data: web::types::State<AppState>) -> HttpResponse {
+    if !data.dev {
+        return not_found().await;
+    }
+
+    let prepared = data.content.load();
+    let sizes = prepared.source_sizes;
+    let section_row = |name: &str, source: usize, output: Option<usize>| {
+        format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            name,
+            if source > 0 { source.to_string() } else { "&ndash;".to_string() },
+            output.map_or("&ndash;".to_string(), |len| len.to_string())
+        )
+    };
+    let rows = [
+        section_row("Markup", sizes.markup, prepared.bodies.html.as_ref().map(Bytes::len)),
+        section_row("Styling", sizes.styling, prepared.bodies.styling.as_ref().map(Bytes::len)),
+        section_row("Script", sizes.script, prepared.bodies.script.as_ref().map(Bytes::len)),
+    ]
+    .concat();
+    let errors: String = prepared
+        .errors
+        .iter()
+        .map(|e| format!("<pre>{}</pre>", parser::escape_html(e)))
+        .collect();
+    let last_reload = SystemTime::now()
+        .duration_since(prepared.prepared_at)
+        .map(|age| format!("{}s ago", age.as_secs()))
+        .unwrap_or_else(|_| "just now".to_string());
+    let source = data
+        .breach_path
+        .as_ref()
+        .map_or("stdin".to_string(), |p| p.display().to_string());
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>B⧸REACH dashboard</title>
+    <style>
+        body {{ font: 14px system-ui, sans-serif; margin: 32px; background: #212121; color: #eee; }}
+        h1 {{ color: #ff5722; }}
+        table {{ border-collapse: collapse; }}
+        td, th {{ padding: 4px 16px 4px 0; text-align: left; }}
+        pre {{ color: #ffab91; white-space: pre-wrap; }}
+    </style>
+</head>
+<body>
+    <h1>B⧸REACH</h1>
+    <p>Source: <code>{}</code></p>
+    <p>Fingerprint: <code>{}</code></p>
+    <p>Last reload: {}</p>
+    <p>Connected clients: {}</p>
+    <table>
+        <tr><th>Section</th><th>Source bytes</th><th>Served bytes</th></tr>
+        {}
+    </table>
+    {}
+</body>
+</html>"#,
+        parser::escape_html(&source),
+        prepared.fingerprint,
+        last_reload,
+        data.ws_clients.load(Ordering::Relaxed),
+        rows,
+        errors
+    );
+    let options = parser::PrepareOptions {
+        reload_mode: parser::ReloadMode::Auto,
+        reload_target: parser::ReloadTarget::Window,
+        ..data.options.clone()
+    };
+    let livereload = parser::livereload_script(&options, prepared.fingerprint);
+    let body = parser::inject_js_script(&body, &livereload);
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .header("Cache-Control", "no-cache")
+        .body(body)
+}
+
+/// Attempts made to deliver a reload message before giving up on a client.
+const RELOAD_SEND_ATTEMPTS: u32 = 3;
+
+/// The message telling clients that the content with `fingerprint` is served:
+/// the configured text, or JSON carrying the fingerprint with `--reload-json`.
+fn reload_message(options: &parser::PrepareOptions, fingerprint: u64) -> String {
+    if options.reload_json {
+        format!(r#"{{"type":"reload","fingerprint":"{}"}}"#, fingerprint)
+    } else {
+        options.reload_message.clone()
+    }
+}
+
+/// Sends the reload message, retrying transient write errors with a short
+/// backoff. Returns false once the client is gone or every attempt failed.
+async fn send_reload(sink: &ws::WsSink, message: &str) -> bool {
+    for attempt in 1..=RELOAD_SEND_ATTEMPTS {
+        match sink.send(ws::Message::Text(message.to_string().into())).await {
+            Ok(()) => return true,
+            Err(e) if sink.io().is_closed() => {
+                tracing::debug!("WebSocket closed while sending reload: {}", e);
+                return false;
+            }
+            Err(e) if attempt < RELOAD_SEND_ATTEMPTS => {
+                tracing::warn!("Failed to send reload message (attempt {}), retrying: {}", attempt, e);
+                ntex::time::sleep(ntex::time::Millis(100 * attempt)).await;
+            }
+            Err(e) => tracing::error!("Failed to send reload message: {}", e),
+        }
+    }
+    false
+}
+
 /// WebSocket handler for live reload functionality
 pub async fn ws_livereload(
     req: web::HttpRequest,
     data: web::types::State<AppState>,
 ) -> Result<HttpResponse, Error> {
     let reload_tx = data.reload_tx.clone();
+    let ws_clients = data.ws_clients.clone();
+    let content = data.content.clone();
+    let options = data.options.clone();
+    // The fingerprint of the content the page was served with
+    let page_fingerprint = req
+        .query_string()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("fingerprint="))
+        .and_then(|value| value.parse::<u64>().ok());
 
     // Create a WebSocket service factory with reload notification support
     let factory = ntex::service::fn_factory_with_config(move |sink: ws::WsSink| {
         let reload_rx = reload_tx.subscribe();
+        ws_clients.fetch_add(1, Ordering::Relaxed);
+        let client_guard = Arc::new(ClientGuard(ws_clients.clone()));
+        let content = content.clone();
+        let options = options.clone();
 
         async move {
             // Clone sink for the reload notification task
             let sink_clone = sink.clone();
 
-            // Spawn a background task to handle reload notifications
+            // Spawn a background task to handle reload notifications. It also watches
+            // for the connection going away, so a client that closes right after the
+            // upgrade doesn't leave the task waiting for the next reload.
             ntex::rt::spawn(async move {
+                let hello = format!("hello {}", parser::LIVERELOAD_PROTOCOL);
+                if let Err(e) = sink_clone.send(ws::Message::Text(hello.into())).await {
+                    tracing::debug!("Failed to greet WebSocket client: {}", e);
+                }
+                // The content may have changed between serving the page and this
+                // connection, with the broadcast sent before the client subscribed
+                let current = content.load().fingerprint;
+                if page_fingerprint.is_some_and(|fingerprint| fingerprint != current) {
+                    tracing::debug!("WebSocket client loaded outdated content, reloading it");
+                    let message = reload_message(&options, current);
+                    if !send_reload(&sink_clone, &message).await {
+                        return;
+                    }
+                }
                 let mut reload_rx = reload_rx;
-                while let Ok(_) = reload_rx.recv().await {
-                    tracing::info!("Sending reload notification to client");
-                    if let Err(e) = sink_clone.send(ws::Message::Text("reload".into())).await {
-                        tracing::error!("Failed to send reload message: {}", e);
-                        break;
+                let disconnected = sink_clone.io().on_disconnect();
+                tokio::pin!(disconnected);
+                loop {
+                    tokio::select! {
+                        _ = &mut disconnected => {
+                            tracing::debug!("WebSocket client disconnected, stopping reload notifications");
+                            break;
+                        }
+                        received = reload_rx.recv() => {
+                            // A client that fell behind by more than the channel capacity
+                            // only needs one reload to catch up, not one per missed change
+                            match received {
+                                Ok(()) => {}
+                                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                                    tracing::debug!("WebSocket client missed {} reload notification(s), coalescing", missed);
+                                }
+                                Err(broadcast::error::RecvError::Closed) => break,
+                            }
+                            tracing::debug!("Sending reload notification to client");
+                            let message = reload_message(&options, content.load().fingerprint);
+                            if !send_reload(&sink_clone, &message).await {
+                                break;
+                            }
+                        }
                     }
                 }
             });
 
             // Create the main service that handles WebSocket frames
             let service = ntex::service::fn_service(move |frame: ws::Frame| {
+                // Keep the guard alive for as long as the connection's service exists
+                let _client_guard = &client_guard;
                 async move {
                     // Handle incoming frames
                     match frame {
-                        ws::Frame::Close(_) => {
-                            tracing::info!("WebSocket connection closed by client");
-                            Ok::<Option<ws::Message>, std::io::Error>(None)
+                        // Answer pings so proxies with idle checks keep the connection open
+                        ws::Frame::Ping(payload) => {
+                            Ok::<Option<ws::Message>, std::io::Error>(Some(ws::Message::Pong(payload)))
+                        }
+                        // Echo the close frame to complete the closing handshake
+                        ws::Frame::Close(reason) => {
+                            tracing::debug!("WebSocket connection closed by client");
+                            Ok::<Option<ws::Message>, std::io::Error>(Some(ws::Message::Close(reason)))
                         }
                         _ => {
                             // Other frames don't need responses
@@ -125,6 +923,111 @@ pub async fn ws_livereload(
         }
     });
 
-    // Start the WebSocket service
-    ntex::web::ws::start(req, factory).await
+    // Start the WebSocket service. ntex validates the Upgrade/Connection headers
+    // (including `Connection: keep-alive, Upgrade` as sent through proxies)
+    let upgrade = req.headers().get("Upgrade").cloned();
+    let connection = req.headers().get("Connection").cloned();
+    let result = ntex::web::ws::start(req, factory).await;
+    if let Err(e) = &result {
+        tracing::warn!(
+            "WebSocket upgrade failed: {} (Upgrade: {:?}, Connection: {:?})",
+            e, upgrade, connection
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    fn state() -> AppState {
+        AppState {
+            content: Arc::new(ArcSwap::from_pointee(parser::PreparedContent::default())),
+            reload_tx: broadcast::channel(16).0,
+            breach_path: None,
+            options: parser::PrepareOptions::default(),
+            dev: false,
+            ws_clients: Arc::new(AtomicUsize::new(0)),
+            on_reload: None,
+            spa: false,
+            ignore_case: false,
+            index_redirect: IndexRedirect::default(),
+            reload_stats: watch::ReloadStats::default(),
+            activity: Arc::new(Activity::default()),
+        }
+    }
+
+    // The connection count is updated by the server's WebSocket task, so it may
+    // lag behind the client by a moment
+    async fn wait_for_clients(clients: &AtomicUsize, expected: usize) {
+        for _ in 0..100 {
+            if clients.load(Ordering::Relaxed) == expected {
+                return;
+            }
+            ntex::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(clients.load(Ordering::Relaxed), expected);
+    }
+
+    #[ntex::test]
+    async fn websocket_upgrade_succeeds_and_survives_an_immediate_close() {
+        let state = state();
+        let clients = state.ws_clients.clone();
+        let srv = test::server(move || {
+            web::App::new()
+                .state(state.clone())
+                .service(web::resource("/ws").route(web::get().to(ws_livereload)))
+        })
+        .await;
+
+        let conn = srv.ws_at("/ws").await.unwrap();
+        assert_eq!(conn.response().status(), StatusCode::SWITCHING_PROTOCOLS);
+        let upgrade = conn.response().headers().get(header::UPGRADE).unwrap();
+        assert!(upgrade.as_bytes().eq_ignore_ascii_case(b"websocket"));
+        wait_for_clients(&clients, 1).await;
+
+        conn.sink().send(ws::Message::Close(None)).await.unwrap();
+        wait_for_clients(&clients, 0).await;
+
+        // The server keeps accepting upgrades after a client went away
+        let conn = srv.ws_at("/ws").await.unwrap();
+        assert_eq!(conn.response().status(), StatusCode::SWITCHING_PROTOCOLS);
+    }
+
+    #[test]
+    fn reload_message_is_plain_text_or_json() {
+        let mut options = parser::PrepareOptions::default();
+        assert_eq!(reload_message(&options, 42), "reload");
+        options.reload_json = true;
+        assert_eq!(reload_message(&options, 42), r#"{"type":"reload","fingerprint":"42"}"#);
+    }
+
+    #[ntex::test]
+    async fn responses_carry_the_exact_content_length() {
+        let state = state();
+        let source = "¦html\n<p>é</p>\n¦css\np { content: \"ü\" }\n¦js\nlet a = 1;\n";
+        let prepared = parser::prepare(parser::parse_breach_content(source), &state.options, None);
+        let bodies = prepared.bodies.clone();
+        state.content.store(Arc::new(prepared));
+        let srv = test::server(move || {
+            web::App::new()
+                .state(state.clone())
+                .service(web::resource("/").route(web::get().to(index)))
+                .service(web::resource("/style.css").route(web::get().to(style_css)))
+                .service(web::resource("/script.js").route(web::get().to(script_js)))
+        })
+        .await;
+
+        for (path, body) in [("/", bodies.html), ("/style.css", bodies.styling), ("/script.js", bodies.script)] {
+            let body = body.unwrap();
+            let mut response = srv.get(path).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+            let length = response.headers().get(header::CONTENT_LENGTH).unwrap();
+            assert_eq!(length.to_str().unwrap(), body.len().to_string(), "{}", path);
+            assert_eq!(response.body().await.unwrap(), body, "{}", path);
+        }
+    }
 }
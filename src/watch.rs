@@ -1,21 +1,130 @@
-use crate::parser;
+use crate::error::BreachError;
+use crate::{fetch, manifest, parser, report};
 use arc_swap::ArcSwap;
-use crossbeam_channel::{unbounded, Sender};
+use crossbeam_channel::{unbounded, Receiver, Sender};
 use notify::{
     Config, Error as NotifyError, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
 };
+use fxhash::{FxHashMap, FxHashSet};
 use std::{
-    path::PathBuf,
-    sync::Arc,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
-use tracing::{error, info};
+use tracing::{debug, error, info, trace, warn};
+
+/// Reload activity since start, shared between the watcher, the reload endpoint
+/// and the health endpoints.
+#[derive(Clone, Default)]
+pub struct ReloadStats {
+    reloads: Arc<AtomicU64>,
+    last_error: Arc<Mutex<Option<(SystemTime, String)>>>,
+    ready: Arc<AtomicBool>,
+}
+
+impl ReloadStats {
+    /// Number of reloads that changed the served content.
+    pub fn reloads(&self) -> u64 {
+        self.reloads.load(Ordering::Relaxed)
+    }
+
+    /// The most recent compile or load error and when it happened.
+    pub fn last_error(&self) -> Option<(SystemTime, String)> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// Whether content prepared without compile errors has been served yet.
+    /// Stays true once set, later errors don't make the server unready.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Marks the server ready if `prepared` compiled without errors.
+    pub fn check_ready(&self, prepared: &parser::PreparedContent) {
+        if prepared.errors.is_empty() {
+            self.ready.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn record_error(&self, error: String) {
+        *self.last_error.lock().unwrap() = Some((SystemTime::now(), error));
+    }
+}
+
+/// Paths written by breach itself (e.g. `--out-dir` outputs). Watchers ignore
+/// events for these so writing outputs next to the sources cannot loop.
+#[derive(Clone, Default)]
+pub struct SelfWrites(Arc<Mutex<FxHashSet<PathBuf>>>);
+
+impl SelfWrites {
+    fn key(path: &Path) -> PathBuf {
+        std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    /// Records a path that breach has written.
+    pub fn record(&self, path: &Path) {
+        if let Ok(mut paths) = self.0.lock() {
+            paths.insert(Self::key(path));
+        }
+    }
+
+    /// Returns true if every path of an event was written by breach.
+    fn covers(&self, event_paths: &[PathBuf]) -> bool {
+        match self.0.lock() {
+            Ok(paths) => {
+                !event_paths.is_empty() && event_paths.iter().all(|p| paths.contains(&Self::key(p)))
+            }
+            Err(_) => false,
+        }
+    }
+}
 
 pub struct EventForwarder {
     tx: Sender<Event>,
 }
 
+/// Collapses identical log lines that repeat within a short window, since some
+/// platforms fire several raw events for a single save.
+struct LogLimiter {
+    window: Duration,
+    last: Option<(String, Instant)>,
+    suppressed: u32,
+}
+
+impl LogLimiter {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last: None,
+            suppressed: 0,
+        }
+    }
+
+    /// Returns true if a line identified by `key` should be logged now.
+    fn allow(&mut self, key: &str) -> bool {
+        let now = Instant::now();
+        if let Some((last_key, at)) = &self.last {
+            if last_key == key && now.duration_since(*at) < self.window {
+                self.suppressed += 1;
+                return false;
+            }
+        }
+        if self.suppressed > 0 {
+            trace!("Suppressed {} repeated watcher event log(s)", self.suppressed);
+            self.suppressed = 0;
+        }
+        self.last = Some((key.to_string(), now));
+        true
+    }
+}
+
 impl notify::EventHandler for EventForwarder {
     fn handle_event(&mut self, event: Result<Event, NotifyError>) {
         if let Ok(event) = event {
@@ -24,78 +133,600 @@ impl notify::EventHandler for EventForwarder {
     }
 }
 
-pub fn watch_file(content: Arc<ArcSwap<parser::PreparedContent>>, path: PathBuf, reload_tx: tokio::sync::broadcast::Sender<()>) {
-    thread::spawn(move || {
-        let mut last_fingerprint: u64 = content.load().fingerprint;
+/// Runs the `--on-reload` command through the shell with the changed file as `$1`
+/// and in `BREACH_PATH`, logging its output. Failures are logged, never propagated.
+fn run_reload_hook(command: &str, path: &Path) {
+    let mut shell = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command).arg(path);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command).arg("sh").arg(path);
+        cmd
+    };
+    debug!("Running reload hook: {}", command);
+    match shell.env("BREACH_PATH", path).stdin(Stdio::null()).output() {
+        Ok(output) => {
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                info!("[on-reload] {}", line);
+            }
+            for line in String::from_utf8_lossy(&output.stderr).lines() {
+                warn!("[on-reload] {}", line);
+            }
+            if !output.status.success() {
+                warn!("Reload hook exited with {}", output.status);
+            }
+        }
+        Err(e) => error!("Failed to run reload hook '{}': {}", command, e),
+    }
+}
 
-        // Convert to absolute path for consistent comparison
-        let absolute_path = path.canonicalize().unwrap_or(path.clone());
+/// Re-reads and re-prepares the .breach file, swapping in the new content and
+/// notifying clients when its fingerprint changed. The `on_reload` hook runs in
+/// between, so anything it generates is ready when pages reload. Returns the
+/// current fingerprint.
+pub fn reload(
+    content: &ArcSwap<parser::PreparedContent>,
+    path: &PathBuf,
+    options: &parser::PrepareOptions,
+    on_reload: Option<&str>,
+    reload_tx: &tokio::sync::broadcast::Sender<()>,
+    stats: &ReloadStats,
+) -> Result<u64, BreachError> {
+    // Compare against what is actually served, which the reload endpoint may have updated
+    let previous = content.load_full();
+    let new_prepared = match parser::load_prepared_from_file(path, options, Some(&previous)) {
+        Ok(prepared) => prepared,
+        // A manifest breaks in ways a single file doesn't (a mapped file missing or
+        // renamed), so keep serving the last sections with the error in the overlay
+        Err(e) if manifest::is_manifest(path) => {
+            error!("Failed to assemble manifest: {}", e);
+            let mut parsed = previous.parsed.clone();
+            parsed.load_error = Some(e.to_string());
+            parser::prepare(parsed, options, Some(&previous))
+        }
+        Err(e) => {
+            stats.record_error(e.to_string());
+            return Err(e);
+        }
+    };
+    Ok(publish(content, &previous, new_prepared, reload_tx, stats, || {
+        if let Some(command) = on_reload {
+            run_reload_hook(command, path);
+        }
+    }))
+}
 
-        let (tx, rx) = unbounded();
-        let forwarder = EventForwarder { tx };
+/// Swaps in `new_prepared` and notifies clients if its fingerprint differs from
+/// `previous`, running `before_notify` in between. Returns the new fingerprint.
+fn publish(
+    content: &ArcSwap<parser::PreparedContent>,
+    previous: &parser::PreparedContent,
+    new_prepared: parser::PreparedContent,
+    reload_tx: &tokio::sync::broadcast::Sender<()>,
+    stats: &ReloadStats,
+    before_notify: impl FnOnce(),
+) -> u64 {
+    stats.check_ready(&new_prepared);
+    report::profile(&new_prepared.timings);
+    let new_fingerprint = new_prepared.fingerprint;
+    debug!("Loaded new content with fingerprint: {} (old: {})", new_fingerprint, previous.fingerprint);
+    if new_fingerprint != previous.fingerprint {
+        report::reloaded(previous, &new_prepared);
+        stats.reloads.fetch_add(1, Ordering::Relaxed);
+        if !new_prepared.errors.is_empty() {
+            stats.record_error(new_prepared.errors.join("\n"));
+        }
+        content.store(Arc::new(new_prepared));
+        before_notify();
 
-        let config = Config::default();
+        // Send reload notification to all connected clients. Having no clients
+        // connected is not an error worth reporting on every save
+        match reload_tx.send(()) {
+            Ok(receivers) => debug!("Reload notification sent to {} receiver(s)", receivers),
+            Err(_) => debug!("No clients connected, reload notification dropped"),
+        }
+    } else {
+        debug!("Fingerprint unchanged, no content update needed");
+        report::unchanged();
+    }
+    new_fingerprint
+}
 
-        let mut watcher = match RecommendedWatcher::new(forwarder, config) {
-            Ok(w) => w,
+/// Fetches `url` again every `interval` for `--poll-remote`, reloading pages
+/// when the content changed. A failed fetch is logged and the last fetched
+/// content stays served.
+pub fn poll_remote(
+    content: Arc<ArcSwap<parser::PreparedContent>>,
+    url: String,
+    interval: Duration,
+    options: parser::PrepareOptions,
+    reload_tx: tokio::sync::broadcast::Sender<()>,
+    stats: ReloadStats,
+) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let previous = content.load_full();
+        match fetch::load_prepared(&url, &options, Some(&previous)) {
+            Ok(new_prepared) => {
+                publish(&content, &previous, new_prepared, &reload_tx, &stats, || {});
+            }
             Err(e) => {
-                error!("Failed to create file watcher: {}", e);
+                warn!("{}, keeping the last fetched content", e);
+                stats.record_error(e.to_string());
+            }
+        }
+    });
+}
+
+/// How long a watched file has to stay quiet after a change before it is reloaded.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// The paths watched by a [`FileWatcher`], counted so a path shared by several
+/// files (a common include, the directory they live in) stays watched until the
+/// last of them lets go of it.
+struct Watches {
+    watcher: RecommendedWatcher,
+    counts: FxHashMap<PathBuf, (usize, RecursiveMode)>,
+}
+
+impl Watches {
+    /// Watches `path`. A path watched non-recursively before is upgraded when
+    /// watched recursively; it is never downgraded while watched.
+    fn add(&mut self, path: &Path, mode: RecursiveMode) -> Result<(), NotifyError> {
+        let upgrade = match self.counts.get(path) {
+            Some((_, current)) => mode == RecursiveMode::Recursive && *current != mode,
+            None => true,
+        };
+        if upgrade {
+            self.watcher.watch(path, mode)?;
+        }
+        let entry = self.counts.entry(path.to_path_buf()).or_insert((0, mode));
+        entry.0 += 1;
+        if upgrade {
+            entry.1 = mode;
+        }
+        Ok(())
+    }
+
+    /// Releases one watch of `path`, unwatching it when it was the last.
+    fn remove(&mut self, path: &Path) -> Result<(), NotifyError> {
+        let Some((count, _)) = self.counts.get_mut(path) else {
+            return Ok(());
+        };
+        *count -= 1;
+        if *count > 0 {
+            return Ok(());
+        }
+        self.counts.remove(path);
+        self.watcher.unwatch(path)
+    }
+}
+
+/// A .breach file (or manifest) watched by a [`FileWatcher`]: the content slot
+/// it is served from and what is needed to reload it.
+struct WatchedFile {
+    content: Arc<ArcSwap<parser::PreparedContent>>,
+    /// The path as given, which may be a symlink.
+    path: PathBuf,
+    /// The canonical path; for a symlinked source this is the link target, which
+    /// is where edits land and what gets reloaded.
+    absolute_path: PathBuf,
+    /// The absolute path of the symlink, when the source is one.
+    link_path: Option<PathBuf>,
+    /// Set when the symlink changed, to re-resolve it on the next reload.
+    relink: bool,
+    /// The `¦include` files currently watched.
+    includes: FxHashSet<PathBuf>,
+    /// The manifest's directory, watched for mapped files being created.
+    manifest_dir: Option<PathBuf>,
+    /// Where the favicon picked up next to the source would be; its directory is
+    /// watched so one created or deleted while running is noticed.
+    favicon_path: PathBuf,
+    /// The canonical `--include-dir` directories, watched whole.
+    include_dirs: Vec<PathBuf>,
+    options: parser::PrepareOptions,
+    on_reload: Option<String>,
+    reload_tx: tokio::sync::broadcast::Sender<()>,
+    stats: ReloadStats,
+    /// When the last relevant change was seen, until the debounced reload runs.
+    last_event_time: Option<Instant>,
+}
+
+impl WatchedFile {
+    /// Returns true if `event` concerns this file, so it should be reloaded.
+    fn is_affected_by(&mut self, event: &Event, should_log: bool) -> bool {
+        if let Some(link) = &self.link_path {
+            if !matches!(event.kind, EventKind::Access(_)) && event.paths.contains(link) {
+                debug!("Symlink {:?} changed", link);
+                self.relink = true;
+                return true;
+            }
+        }
+        // Reloads whose output doesn't change keep the same fingerprint,
+        // so only pages actually using the partial reload
+        if !matches!(event.kind, EventKind::Access(_))
+            && event.paths.iter().any(|p| self.include_dirs.iter().any(|dir| p.starts_with(dir)))
+        {
+            debug!("Change in include directory: {:?}", event.paths);
+            return true;
+        }
+        if let (Some(dir), EventKind::Create(_)) = (&self.manifest_dir, &event.kind) {
+            if event.paths.iter().any(|p| p.parent() == Some(dir.as_path())) {
+                debug!("File created next to the manifest: {:?}", event.paths);
+                return true;
+            }
+        }
+        if !matches!(event.kind, EventKind::Access(_)) && event.paths.contains(&self.favicon_path) {
+            debug!("Favicon changed: {:?}", self.favicon_path);
+            return true;
+        }
+        if let EventKind::Modify(_) = event.kind {
+            if event.paths.iter().any(|p| *p == self.absolute_path || self.includes.contains(p)) {
+                if should_log {
+                    debug!("File modification detected for watched file: {:?}", self.absolute_path);
+                }
+                return true;
+            } else if should_log {
+                trace!("File modification detected but not for watched file. Watched: {:?}, Modified: {:?}", self.absolute_path, event.paths);
+            }
+        }
+        false
+    }
+
+    // Brings the watched `¦include` files in line with the dependencies of the
+    // content currently served, which change whenever includes are edited
+    fn sync_dependencies(&mut self, watches: &mut Watches) {
+        let current: FxHashSet<PathBuf> = self.content.load().parsed.dependencies.iter().cloned().collect();
+        for path in self.includes.difference(&current) {
+            if let Err(e) = watches.remove(path) {
+                debug!("Failed to unwatch include {:?}: {}", path, e);
+            }
+        }
+        for path in current.difference(&self.includes) {
+            match watches.add(path, RecursiveMode::NonRecursive) {
+                Ok(()) => debug!("Watching include {:?}", path),
+                Err(e) => error!("Failed to watch include {:?}: {}", path, e),
+            }
+        }
+        self.includes = current;
+    }
+
+    // Re-resolves a symlinked source after the link changed and moves the file watch
+    // to the new target
+    fn retarget(&mut self, watches: &mut Watches) {
+        let new_target = match self.path.canonicalize() {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Failed to resolve symlink {:?}: {}", self.path, e);
                 return;
             }
         };
+        if new_target == self.absolute_path {
+            return;
+        }
+        info!("Symlink {:?} now points to {:?}", self.path, new_target);
+        if let Err(e) = watches.remove(&self.absolute_path) {
+            debug!("Failed to unwatch previous target {:?}: {}", self.absolute_path, e);
+        }
+        if let Err(e) = watches.add(&new_target, RecursiveMode::NonRecursive) {
+            error!("Failed to watch file: {}", e);
+        }
+        self.set_favicon_path(watches, new_target.with_file_name(parser::FAVICON_NAME));
+        self.absolute_path = new_target;
+    }
 
-        if let Err(e) = watcher.watch(&absolute_path, RecursiveMode::NonRecursive) {
+    // Moves the watch on the favicon's directory along with the favicon path
+    fn set_favicon_path(&mut self, watches: &mut Watches, favicon_path: PathBuf) {
+        if let Some(dir) = favicon_path.parent() {
+            if let Err(e) = watches.add(dir, RecursiveMode::NonRecursive) {
+                error!("Failed to watch favicon directory {:?}: {}", dir, e);
+            }
+        }
+        let previous = std::mem::replace(&mut self.favicon_path, favicon_path);
+        if let Some(dir) = previous.parent() {
+            if let Err(e) = watches.remove(dir) {
+                debug!("Failed to unwatch previous favicon directory {:?}: {}", dir, e);
+            }
+        }
+    }
+}
+
+/// Watches any number of .breach files from a single thread with one OS
+/// watcher, reloading each into its own content slot. Events are dispatched to
+/// the files they concern and debounced per file.
+pub struct FileWatcher {
+    watches: Watches,
+    rx: Receiver<Event>,
+    files: Vec<WatchedFile>,
+    self_writes: SelfWrites,
+    log_limiter: LogLimiter,
+}
+
+impl FileWatcher {
+    /// Creates a watcher with no files. Changes to paths in `self_writes` are
+    /// ignored.
+    pub fn new(self_writes: SelfWrites) -> Result<Self, NotifyError> {
+        let (tx, rx) = unbounded();
+        let watcher = RecommendedWatcher::new(EventForwarder { tx }, Config::default())?;
+        Ok(FileWatcher {
+            watches: Watches { watcher, counts: FxHashMap::default() },
+            rx,
+            files: Vec::new(),
+            self_writes,
+            log_limiter: LogLimiter::new(Duration::from_millis(500)),
+        })
+    }
+
+    /// Starts watching the .breach file at `path`, along with its includes, its
+    /// symlink or manifest directory and the include directories of `options`.
+    /// Changes reload it into `content` and notify `reload_tx`.
+    pub fn add(
+        &mut self,
+        content: Arc<ArcSwap<parser::PreparedContent>>,
+        path: PathBuf,
+        options: parser::PrepareOptions,
+        on_reload: Option<String>,
+        reload_tx: tokio::sync::broadcast::Sender<()>,
+        stats: ReloadStats,
+    ) {
+        // Convert to absolute path for consistent comparison
+        let absolute_path = path.canonicalize().unwrap_or(path.clone());
+        let link_path = fs::symlink_metadata(&path)
+            .is_ok_and(|meta| meta.file_type().is_symlink())
+            .then(|| std::path::absolute(&path).unwrap_or(path.clone()));
+
+        if let Err(e) = self.watches.add(&absolute_path, RecursiveMode::NonRecursive) {
             error!("Failed to watch file: {}", e);
             return;
         }
 
-        let mut last_event_time: Option<Instant> = None;
+        // Watching the link's directory notices the link being repointed or replaced
+        if let Some(link) = &link_path {
+            debug!("{:?} is a symlink to {:?}", link, absolute_path);
+            if let Some(dir) = link.parent() {
+                if let Err(e) = self.watches.add(dir, RecursiveMode::NonRecursive) {
+                    error!("Failed to watch symlink directory {:?}: {}", dir, e);
+                }
+            }
+        }
+
+        // A manifest may name files that don't exist yet; watching its directory
+        // notices them being created
+        let manifest_dir = manifest::is_manifest(&absolute_path)
+            .then(|| absolute_path.parent().map(Path::to_path_buf))
+            .flatten();
+        if let Some(dir) = &manifest_dir {
+            if let Err(e) = self.watches.add(dir, RecursiveMode::NonRecursive) {
+                error!("Failed to watch manifest directory {:?}: {}", dir, e);
+            }
+        }
+
+        // Include directories are watched whole, as SCSS may import any file in them
+        let include_dirs: Vec<PathBuf> = options
+            .include_dirs
+            .iter()
+            .filter_map(|dir| dir.canonicalize().ok())
+            .collect();
+        for dir in &include_dirs {
+            if let Err(e) = self.watches.add(dir, RecursiveMode::Recursive) {
+                error!("Failed to watch include directory {:?}: {}", dir, e);
+            }
+        }
 
         info!("File watcher started for: {:?}", path);
+        let mut file = WatchedFile {
+            content,
+            path,
+            absolute_path,
+            link_path,
+            relink: false,
+            includes: FxHashSet::default(),
+            manifest_dir,
+            favicon_path: PathBuf::new(),
+            include_dirs,
+            options,
+            on_reload,
+            reload_tx,
+            stats,
+            last_event_time: None,
+        };
+        file.set_favicon_path(&mut self.watches, file.absolute_path.with_file_name(parser::FAVICON_NAME));
+        file.sync_dependencies(&mut self.watches);
+        self.files.push(file);
+    }
 
+    /// Runs the watcher on its own thread.
+    pub fn spawn(self) {
+        thread::spawn(move || self.run());
+    }
+
+    fn run(mut self) {
+        let rx = self.rx.clone();
         loop {
             crossbeam_channel::select! {
                 recv(rx) -> event => {
                     if let Ok(event) = event {
-                        info!("File watcher event received: {:?} for paths: {:?}", event.kind, event.paths);
-                        if let EventKind::Modify(_) = event.kind {
-                            if event.paths.contains(&absolute_path) {
-                                info!("File modification detected for watched file: {:?}", absolute_path);
-                                last_event_time = Some(Instant::now());
-                            } else {
-                                info!("File modification detected but not for watched file. Watched: {:?}, Modified: {:?}", absolute_path, event.paths);
-                            }
-                        }
+                        self.dispatch(&event);
                     } else {
                         error!("File watcher received error event: {:?}", event);
                     }
                 }
+                default(Duration::from_millis(50)) => self.reload_pending(),
+            }
+        }
+    }
+
+    /// Marks every file `event` concerns as changed.
+    fn dispatch(&mut self, event: &Event) {
+        if self.self_writes.covers(&event.paths) {
+            trace!("Ignoring event for self-written paths: {:?}", event.paths);
+            return;
+        }
+        let log_key = format!("{:?} {:?}", event.kind, event.paths);
+        let should_log = self.log_limiter.allow(&log_key);
+        if should_log {
+            trace!("File watcher event received: {:?} for paths: {:?}", event.kind, event.paths);
+        }
+        for file in &mut self.files {
+            if file.is_affected_by(event, should_log) {
+                file.last_event_time = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Reloads the files whose last change is older than the debounce period.
+    fn reload_pending(&mut self) {
+        for file in &mut self.files {
+            if file.last_event_time.is_none_or(|time| time.elapsed() < DEBOUNCE) {
+                continue;
+            }
+            debug!("Processing pending file change after debounce period");
+            file.last_event_time = None;
+            if std::mem::take(&mut file.relink) {
+                file.retarget(&mut self.watches);
+            }
+            if let Err(e) = reload(
+                &file.content,
+                &file.absolute_path,
+                &file.options,
+                file.on_reload.as_deref(),
+                &file.reload_tx,
+                &file.stats,
+            ) {
+                error!("Failed to load updated breach file: {}", e);
+            }
+            file.sync_dependencies(&mut self.watches);
+        }
+    }
+}
+
+/// Watches the single .breach file at `path` on its own [`FileWatcher`] thread.
+pub fn watch_file(
+    content: Arc<ArcSwap<parser::PreparedContent>>,
+    path: PathBuf,
+    options: parser::PrepareOptions,
+    on_reload: Option<String>,
+    reload_tx: tokio::sync::broadcast::Sender<()>,
+    self_writes: SelfWrites,
+    stats: ReloadStats,
+) {
+    let mut watcher = match FileWatcher::new(self_writes) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("Failed to create file watcher: {}", e);
+            return;
+        }
+    };
+    watcher.add(content, path, options, on_reload, reload_tx, stats);
+    watcher.spawn();
+}
+
+// The directory a glob has to be watched from: its leading components up to the
+// first one containing a wildcard, or the parent directory for a literal path
+fn glob_base_dir(pattern: &Path) -> PathBuf {
+    let mut base = PathBuf::new();
+    for component in pattern.components() {
+        if component.as_os_str().to_string_lossy().contains(['*', '?', '[']) {
+            break;
+        }
+        base.push(component);
+    }
+    if base == pattern {
+        base.pop();
+    }
+    base
+}
+
+/// Watches companion files matched by `--watch-extra` globs and sends a plain
+/// reload (no re-prepare) when one of them changes. These watches are kept apart
+/// from the .breach watcher since the files never feed into the prepared content.
+pub fn watch_extra(
+    patterns: Vec<String>,
+    reload_tx: tokio::sync::broadcast::Sender<()>,
+    self_writes: SelfWrites,
+) {
+    if patterns.is_empty() {
+        return;
+    }
+
+    thread::spawn(move || {
+        let cwd = std::env::current_dir().unwrap_or_default();
+
+        // Watch the base directory of each glob rather than the matched files, so
+        // files created later or replaced by atomic saves are still noticed
+        let mut matchers = Vec::new();
+        let mut dirs: BTreeMap<PathBuf, RecursiveMode> = BTreeMap::new();
+        for pattern in &patterns {
+            let absolute = cwd.join(pattern);
+            match glob::Pattern::new(&absolute.to_string_lossy()) {
+                Ok(matcher) => matchers.push(matcher),
+                Err(e) => {
+                    error!("Invalid --watch-extra pattern '{}': {}", pattern, e);
+                    continue;
+                }
+            }
+            let mode = if pattern.contains("**") {
+                RecursiveMode::Recursive
+            } else {
+                RecursiveMode::NonRecursive
+            };
+            let dir = glob_base_dir(&absolute);
+            let entry = dirs.entry(dir).or_insert(mode);
+            if mode == RecursiveMode::Recursive {
+                *entry = mode;
+            }
+        }
+
+        let (tx, rx) = unbounded();
+        let forwarder = EventForwarder { tx };
+
+        let mut watcher = match RecommendedWatcher::new(forwarder, Config::default()) {
+            Ok(w) => w,
+            Err(e) => {
+                error!("Failed to create extra file watcher: {}", e);
+                return;
+            }
+        };
+
+        for (dir, mode) in &dirs {
+            match watcher.watch(dir, *mode) {
+                Ok(()) => info!("Watching extra files in {:?}", dir),
+                Err(e) => error!("Failed to watch {:?}: {}", dir, e),
+            }
+        }
+
+        let mut last_event_time: Option<Instant> = None;
+        let mut log_limiter = LogLimiter::new(Duration::from_millis(500));
+
+        loop {
+            crossbeam_channel::select! {
+                recv(rx) -> event => {
+                    let Ok(event) = event else {
+                        error!("Extra file watcher channel closed");
+                        return;
+                    };
+                    if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) {
+                        continue;
+                    }
+                    if self_writes.covers(&event.paths) {
+                        trace!("Ignoring event for self-written paths: {:?}", event.paths);
+                        continue;
+                    }
+                    if let Some(changed) = event.paths.iter().find(|p| matchers.iter().any(|m| m.matches_path(p))) {
+                        if log_limiter.allow(&changed.to_string_lossy()) {
+                            debug!("Extra watched file changed: {:?}", changed);
+                        }
+                        last_event_time = Some(Instant::now());
+                    }
+                }
                 default(Duration::from_millis(50)) => {
-                    // Check if we have a pending event and enough time has passed
                     if let Some(event_time) = last_event_time {
-                        if event_time.elapsed() >= Duration::from_millis(100) {
-                            info!("Processing pending file change after debounce period");
-                            match parser::load_prepared_from_file(&path) {
-                                Ok(new_prepared) => {
-                                    let new_fingerprint = new_prepared.fingerprint;
-                                    info!("Loaded new content with fingerprint: {} (old: {})", new_fingerprint, last_fingerprint);
-                                    if new_fingerprint != last_fingerprint {
-                                        content.store(Arc::new(new_prepared));
-                                        last_fingerprint = new_fingerprint;
-                                        info!("Breach file updated and content refreshed. Sending reload notification.");
-
-                                        // Send reload notification to all connected clients
-                                        match reload_tx.send(()) {
-                                            Ok(_) => info!("Reload notification sent successfully"),
-                                            Err(e) => error!("Failed to send reload notification: {}", e),
-                                        }
-                                    } else {
-                                        info!("Fingerprint unchanged, no content update needed");
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Failed to load updated breach file: {}", e);
-                                }
+                        if event_time.elapsed() >= DEBOUNCE {
+                            info!("Extra watched file changed. Sending reload notification.");
+                            if let Err(e) = reload_tx.send(()) {
+                                error!("Failed to send reload notification: {}", e);
                             }
                             last_event_time = None;
                         }
@@ -105,3 +736,122 @@ pub fn watch_file(content: Arc<ArcSwap<parser::PreparedContent>>, path: PathBuf,
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, ModifyKind, RemoveKind};
+
+    // A fresh directory for one test, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("breach-watch-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir.canonicalize().unwrap())
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn options() -> parser::PrepareOptions {
+        parser::PrepareOptions { livereload: false, ..Default::default() }
+    }
+
+    fn markup(content: &ArcSwap<parser::PreparedContent>) -> Option<String> {
+        content.load().parsed.markup.clone()
+    }
+
+    // Watches `path` the way `watch_file` does, without starting the thread
+    fn watch(path: &Path) -> (FileWatcher, Arc<ArcSwap<parser::PreparedContent>>) {
+        let prepared = parser::load_prepared_from_file(&path.to_path_buf(), &options(), None).unwrap();
+        let content = Arc::new(ArcSwap::from_pointee(prepared));
+        let mut watcher = FileWatcher::new(SelfWrites::default()).unwrap();
+        let (reload_tx, _) = tokio::sync::broadcast::channel(16);
+        watcher.add(content.clone(), path.to_path_buf(), options(), None, reload_tx, ReloadStats::default());
+        (watcher, content)
+    }
+
+    // Feeds `event` to the watcher and runs the reload it schedules
+    fn deliver(watcher: &mut FileWatcher, event: Event) {
+        watcher.dispatch(&event);
+        thread::sleep(DEBOUNCE + Duration::from_millis(20));
+        watcher.reload_pending();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_source_is_watched_and_reloaded_at_its_target() {
+        let dir = TempDir::new("symlink");
+        fs::write(dir.0.join("a.breach"), "¦html\n<p>a</p>\n").unwrap();
+        let link = dir.0.join("page.breach");
+        std::os::unix::fs::symlink(dir.0.join("a.breach"), &link).unwrap();
+
+        let (mut watcher, content) = watch(&link);
+        assert_eq!(markup(&content).as_deref(), Some("<p>a</p>"));
+        assert_eq!(watcher.files[0].absolute_path, dir.0.join("a.breach"));
+        assert_eq!(watcher.files[0].link_path.as_deref(), Some(link.as_path()));
+
+        // An edit lands in the target
+        fs::write(dir.0.join("a.breach"), "¦html\n<p>edited</p>\n").unwrap();
+        deliver(&mut watcher, Event::new(EventKind::Modify(ModifyKind::Any)).add_path(dir.0.join("a.breach")));
+        assert_eq!(markup(&content).as_deref(), Some("<p>edited</p>"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn repointed_symlink_moves_to_the_new_target() {
+        let dir = TempDir::new("relink");
+        fs::write(dir.0.join("a.breach"), "¦html\n<p>a</p>\n").unwrap();
+        fs::write(dir.0.join("b.breach"), "¦html\n<p>b</p>\n").unwrap();
+        let link = dir.0.join("page.breach");
+        std::os::unix::fs::symlink(dir.0.join("a.breach"), &link).unwrap();
+        let (mut watcher, content) = watch(&link);
+
+        fs::remove_file(&link).unwrap();
+        std::os::unix::fs::symlink(dir.0.join("b.breach"), &link).unwrap();
+        deliver(&mut watcher, Event::new(EventKind::Create(CreateKind::File)).add_path(link.clone()));
+        assert_eq!(markup(&content).as_deref(), Some("<p>b</p>"));
+        assert_eq!(watcher.files[0].absolute_path, dir.0.join("b.breach"));
+
+        // The previous target no longer concerns the page
+        let stale = Event::new(EventKind::Modify(ModifyKind::Any)).add_path(dir.0.join("a.breach"));
+        assert!(!watcher.files[0].is_affected_by(&stale, false));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reading_the_symlink_is_not_a_change() {
+        let dir = TempDir::new("access");
+        fs::write(dir.0.join("a.breach"), "¦html\n<p>a</p>\n").unwrap();
+        let link = dir.0.join("page.breach");
+        std::os::unix::fs::symlink(dir.0.join("a.breach"), &link).unwrap();
+        let (mut watcher, _) = watch(&link);
+        let access = Event::new(EventKind::Access(AccessKind::Any)).add_path(link);
+        assert!(!watcher.files[0].is_affected_by(&access, false));
+    }
+
+    #[test]
+    fn favicon_created_or_removed_while_running_reloads() {
+        let dir = TempDir::new("favicon");
+        let source = dir.0.join("page.breach");
+        fs::write(&source, "¦html\n<p>a</p>\n").unwrap();
+        let (mut watcher, content) = watch(&source);
+        assert!(content.load().bodies.favicon.is_none());
+
+        let favicon = dir.0.join(parser::FAVICON_NAME);
+        fs::write(&favicon, b"icon").unwrap();
+        deliver(&mut watcher, Event::new(EventKind::Create(CreateKind::File)).add_path(favicon.clone()));
+        assert_eq!(content.load().bodies.favicon.as_deref(), Some(&b"icon"[..]));
+
+        fs::remove_file(&favicon).unwrap();
+        deliver(&mut watcher, Event::new(EventKind::Remove(RemoveKind::File)).add_path(favicon));
+        assert!(content.load().bodies.favicon.is_none());
+    }
+}